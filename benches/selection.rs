@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+use wallrustler::collection::Shares;
+use wallrustler::rotation::Rotation;
+use wallrustler::selection::{new_rng, Constraints, StrategyKind, Weighting};
+use wallrustler::{sync_wallpapers, Scan, Wallpaper, DEFAULT_REMOVED_RETENTION};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const PICKS: u32 = 100;
+
+fn library(size: usize) -> Vec<Wallpaper> {
+    (0..size)
+        .map(|i| {
+            let mut wallpaper = Wallpaper::new(format!("{i:06}.jpg"));
+            wallpaper.count = i % 5_000;
+            wallpaper
+        })
+        .collect()
+}
+
+/// Time to build the rotation and then per pick
+fn bench_pick(size: usize) -> (Duration, Duration) {
+    let mut wallpapers = library(size);
+    let mut strategy = StrategyKind::Decay.build(Weighting::default());
+    let constraints = Constraints {
+        no_repeat: 10,
+        fairness: Some(size * 2),
+        ..Default::default()
+    };
+    let mut rng = new_rng(Some(0));
+
+    let start = Instant::now();
+    let mut rotation = Rotation::new(
+        &wallpapers,
+        strategy.as_mut(),
+        &constraints,
+        &Shares::default(),
+    );
+    let build = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..PICKS {
+        rotation.pick(&mut wallpapers, strategy.as_mut(), &mut rng);
+    }
+    (build, start.elapsed() / PICKS)
+}
+
+fn bench_sync(size: usize) -> Duration {
+    let dir = std::env::temp_dir().join(format!("wallrustler-bench-{size}"));
+    std::fs::create_dir_all(&dir).unwrap();
+    for i in 0..size {
+        // Scanning checks the content, a JPEG signature is all it looks at
+        std::fs::write(dir.join(format!("{i:06}.jpg")), [0xff, 0xd8, 0xff]).unwrap();
+    }
+    let wallpapers = library(size);
+
+    let start = Instant::now();
+    let wallpapers = sync_wallpapers(
+        &dir,
+        wallpapers,
+        &Scan::default(),
+        DEFAULT_REMOVED_RETENTION,
+    );
+    let elapsed = start.elapsed();
+
+    assert_eq!(wallpapers.len(), size);
+    std::fs::remove_dir_all(&dir).unwrap();
+    elapsed
+}
+
+fn main() {
+    for size in SIZES {
+        let (build, pick) = bench_pick(size);
+        println!("build {size:>7} wallpapers: {build:>10.3?}");
+        println!("pick  {size:>7} wallpapers: {pick:>10.3?}");
+    }
+    for size in SIZES {
+        println!("sync  {size:>7} wallpapers: {:>10.3?}", bench_sync(size));
+    }
+}
@@ -0,0 +1,153 @@
+//! Collections are the subdirectories of a recursively scanned wallpaper directory, named by
+//! their path relative to it. A collection holds everything below it, so `nature` also holds
+//! `nature/forest`, and the wallpapers at the top level make up the `.` collection.
+//!
+//! Weighted collections are picked in two steps, first a collection by its weight and then a
+//! wallpaper within it by the selection strategy.
+
+use std::collections::HashMap;
+
+pub const ROOT: &str = ".";
+
+pub fn contains(collection: &str, file_name: &str) -> bool {
+    match collection {
+        ROOT => !file_name.contains('/'),
+        collection => file_name
+            .strip_prefix(collection.trim_end_matches('/'))
+            .is_some_and(|rest| rest.starts_with('/')),
+    }
+}
+
+/// Collections wallpapers may be picked from
+#[derive(Debug, Default, Clone)]
+pub struct CollectionFilter {
+    /// Only these collections if set, every one otherwise
+    pub enabled: Option<Vec<String>>,
+    pub disabled: Vec<String>,
+}
+
+impl CollectionFilter {
+    pub fn allows(&self, file_name: &str) -> bool {
+        let enabled = self.enabled.as_ref().is_none_or(|enabled| {
+            enabled
+                .iter()
+                .any(|collection| contains(collection, file_name))
+        });
+
+        enabled
+            && !self
+                .disabled
+                .iter()
+                .any(|collection| contains(collection, file_name))
+    }
+}
+
+/// Name of the weight given to the wallpapers outside every weighted collection
+pub const REST: &str = "*";
+
+/// Weight of the wallpapers outside every weighted collection when `REST` isn't given
+pub const DEFAULT_REST_WEIGHT: f64 = 1.0;
+
+/// Share of the picks every collection gets whatever its size. Source directories can be
+/// weighted too, by their directory name
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CollectionWeights {
+    pub weights: Vec<(String, f64)>,
+}
+
+impl CollectionWeights {
+    /// Index into `weights` of the most specific collection the wallpaper belongs to
+    fn collection_of(&self, source_name: &str, file_name: &str) -> Option<usize> {
+        let source_file_name = format!("{source_name}/{file_name}");
+        let matching = self.weights.iter().enumerate().filter(|(_, (name, _))| {
+            contains(name, file_name) || contains(name, &source_file_name)
+        });
+
+        matching
+            .max_by_key(|(_, (name, _))| name.len())
+            .map(|(index, _)| index)
+            .or_else(|| self.weights.iter().position(|(name, _)| name == REST))
+    }
+
+    /// Collections of the wallpapers, given by source directory name and file name
+    pub fn resolve<'a>(&self, wallpapers: impl Iterator<Item = (&'a str, &'a str)>) -> Shares {
+        let mut resolved = self.clone();
+        if !resolved.weights.is_empty() && !resolved.weights.iter().any(|(name, _)| name == REST) {
+            resolved
+                .weights
+                .push((REST.to_owned(), DEFAULT_REST_WEIGHT));
+        }
+
+        Shares {
+            weights: resolved.weights.iter().map(|(_, weight)| *weight).collect(),
+            collection_of: wallpapers
+                .map(|(source_name, file_name)| resolved.collection_of(source_name, file_name))
+                .collect(),
+        }
+    }
+}
+
+/// Collection weights resolved for a list of wallpapers. Without weights every wallpaper is in
+/// a single collection
+#[derive(Debug, Default)]
+pub struct Shares {
+    weights: Vec<f64>,
+    collection_of: Vec<Option<usize>>,
+}
+
+impl Shares {
+    /// Collection of the wallpaper at `index`, as an index into `weights()`
+    pub fn collection_of(&self, index: usize) -> Option<usize> {
+        if self.weights.is_empty() {
+            return Some(0);
+        }
+        self.collection_of[index]
+    }
+
+    /// Weight of every collection
+    pub fn weights(&self) -> Vec<f64> {
+        if self.weights.is_empty() {
+            return vec![1.0];
+        }
+        self.weights.clone()
+    }
+
+    /// Candidates split by collection, with the weight of each collection
+    fn groups(&self, candidates: &[usize]) -> Vec<(f64, Vec<usize>)> {
+        let weights = self.weights();
+        let mut groups = vec![vec![]; weights.len()];
+        for &index in candidates {
+            if let Some(collection) = self.collection_of(index) {
+                groups[collection].push(index);
+            }
+        }
+        weights
+            .into_iter()
+            .zip(groups)
+            .filter(|(_, group)| !group.is_empty())
+            .collect()
+    }
+
+    /// Odds of every candidate, in the same order, given the odds within each collection
+    pub fn probabilities(
+        &self,
+        candidates: &[usize],
+        within: impl Fn(&[usize]) -> Vec<f64>,
+    ) -> Vec<f64> {
+        let groups = self.groups(candidates);
+        let total: f64 = groups.iter().map(|(weight, _)| weight).sum();
+        let positions: HashMap<usize, usize> = candidates
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| (index, position))
+            .collect();
+
+        let mut probabilities = vec![0.0; candidates.len()];
+        for (weight, group) in groups {
+            for (index, probability) in group.iter().zip(within(&group)) {
+                probabilities[positions[index]] = weight / total * probability;
+            }
+        }
+        probabilities
+    }
+}
@@ -0,0 +1,147 @@
+//! Human-readable copies of the state, for inspecting, editing and moving it between machines.
+
+use crate::Wallpaper;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StateFormat {
+    Json,
+    Toml,
+    Csv,
+}
+
+impl StateFormat {
+    pub fn from_name(name: &str) -> Option<StateFormat> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(StateFormat::Json),
+            "toml" => Some(StateFormat::Toml),
+            "csv" => Some(StateFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &std::path::Path) -> Option<StateFormat> {
+        StateFormat::from_name(path.extension()?.to_str()?)
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Json(serde_json::Error),
+    TomlSerialize(toml::ser::Error),
+    TomlDeserialize(toml::de::Error),
+    Csv(csv::Error),
+    InvalidHash(String),
+}
+
+/// Flat record of a wallpaper, the hash is written in hex as TOML integers are signed
+#[derive(Serialize, Deserialize)]
+struct WallpaperRecord {
+    file_name: String,
+    count: usize,
+    rating: u8,
+    banned: bool,
+    in_bag: bool,
+    last_shown: Option<u64>,
+    rotations_since_shown: usize,
+    hash: Option<String>,
+    size: u64,
+    mtime: u64,
+    #[serde(default)]
+    seconds_shown: u64,
+    #[serde(default)]
+    edited: u64,
+    #[serde(default)]
+    removed: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TomlState {
+    wallpaper: Vec<WallpaperRecord>,
+}
+
+impl From<&Wallpaper> for WallpaperRecord {
+    fn from(wallpaper: &Wallpaper) -> Self {
+        WallpaperRecord {
+            file_name: wallpaper.file_name.clone(),
+            count: wallpaper.count,
+            rating: wallpaper.rating,
+            banned: wallpaper.banned,
+            in_bag: wallpaper.in_bag,
+            last_shown: wallpaper.last_shown,
+            rotations_since_shown: wallpaper.rotations_since_shown,
+            hash: wallpaper.hash.map(|hash| format!("{hash:016x}")),
+            size: wallpaper.size,
+            mtime: wallpaper.mtime,
+            seconds_shown: wallpaper.seconds_shown,
+            edited: wallpaper.edited,
+            removed: wallpaper.removed,
+        }
+    }
+}
+
+impl TryFrom<WallpaperRecord> for Wallpaper {
+    type Error = ExportError;
+
+    fn try_from(record: WallpaperRecord) -> Result<Self, Self::Error> {
+        let hash = match record.hash.filter(|hash| !hash.is_empty()) {
+            Some(hash) => {
+                Some(u64::from_str_radix(&hash, 16).map_err(|_| ExportError::InvalidHash(hash))?)
+            }
+            None => None,
+        };
+
+        let mut wallpaper = Wallpaper::new(record.file_name);
+        wallpaper.count = record.count;
+        wallpaper.rating = record.rating.clamp(1, crate::MAX_RATING);
+        wallpaper.banned = record.banned;
+        wallpaper.in_bag = record.in_bag;
+        wallpaper.last_shown = record.last_shown;
+        wallpaper.rotations_since_shown = record.rotations_since_shown;
+        wallpaper.hash = hash;
+        wallpaper.size = record.size;
+        wallpaper.mtime = record.mtime;
+        wallpaper.seconds_shown = record.seconds_shown;
+        wallpaper.edited = record.edited;
+        wallpaper.removed = record.removed;
+        Ok(wallpaper)
+    }
+}
+
+pub fn export(wallpapers: &[Wallpaper], format: StateFormat) -> Result<String, ExportError> {
+    let records: Vec<WallpaperRecord> = wallpapers.iter().map(WallpaperRecord::from).collect();
+
+    match format {
+        StateFormat::Json => serde_json::to_string_pretty(&records).map_err(ExportError::Json),
+        StateFormat::Toml => {
+            toml::to_string(&TomlState { wallpaper: records }).map_err(ExportError::TomlSerialize)
+        }
+        StateFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for record in records {
+                writer.serialize(record).map_err(ExportError::Csv)?;
+            }
+            let csv = writer
+                .into_inner()
+                .map_err(|err| ExportError::Csv(err.into_error().into()))?;
+            Ok(String::from_utf8_lossy(&csv).into_owned())
+        }
+    }
+}
+
+pub fn import(input: &str, format: StateFormat) -> Result<Vec<Wallpaper>, ExportError> {
+    let records: Vec<WallpaperRecord> = match format {
+        StateFormat::Json => serde_json::from_str(input).map_err(ExportError::Json)?,
+        StateFormat::Toml => {
+            toml::from_str::<TomlState>(input)
+                .map_err(ExportError::TomlDeserialize)?
+                .wallpaper
+        }
+        StateFormat::Csv => csv::Reader::from_reader(input.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(ExportError::Csv)?,
+    };
+
+    records.into_iter().map(Wallpaper::try_from).collect()
+}
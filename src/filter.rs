@@ -0,0 +1,104 @@
+//! Which files of a wallpaper directory are scanned, decided by the include and exclude patterns
+//! from the options and by the `.wallrustlerignore` file at the top of the directory. All of them
+//! use gitignore syntax, so `*-thumb.jpg` matches at any depth and `drafts/` only directories.
+
+use crate::format::ImageFormat;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+pub const IGNORE_FILE_NAME: &str = ".wallrustlerignore";
+
+/// Why a file or directory was skipped
+#[derive(Debug, PartialEq)]
+pub enum IgnoreReason {
+    /// Matched a pattern of the ignore file
+    IgnoreFile(String),
+    /// Matched an exclude pattern
+    Excluded(String),
+    /// Matched none of the include patterns
+    NotIncluded,
+    /// Has the extension of an image format but not its content
+    NotAnImage(ImageFormat),
+}
+
+impl std::fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IgnoreReason::IgnoreFile(pattern) => write!(f, "{IGNORE_FILE_NAME} pattern {pattern}"),
+            IgnoreReason::Excluded(pattern) => write!(f, "--exclude={pattern}"),
+            IgnoreReason::NotIncluded => write!(f, "no --include pattern matches"),
+            IgnoreReason::NotAnImage(format) => {
+                write!(f, "content isn't a {} image", format.name())
+            }
+        }
+    }
+}
+
+/// Whether a pattern given in the options is valid gitignore syntax
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    GitignoreBuilder::new("")
+        .add_line(None, pattern)
+        .and_then(|builder| builder.build())
+        .is_ok()
+}
+
+pub struct Filter {
+    ignore_file: Gitignore,
+    exclude: Gitignore,
+    include: Option<Gitignore>,
+}
+
+impl Filter {
+    pub fn new(
+        wallpaper_dir_path: &std::path::Path,
+        include: &[String],
+        exclude: &[String],
+    ) -> Filter {
+        let ignore_file_path = wallpaper_dir_path.join(IGNORE_FILE_NAME);
+        let mut builder = GitignoreBuilder::new(wallpaper_dir_path);
+        if ignore_file_path.exists() {
+            if let Some(err) = builder.add(&ignore_file_path) {
+                eprintln!("Warning: {}: {err}", ignore_file_path.display());
+            }
+        }
+        let ignore_file = builder.build().unwrap_or_else(|err| {
+            eprintln!("Warning: {}: {err}", ignore_file_path.display());
+            Gitignore::empty()
+        });
+
+        let patterns = |patterns: &[String]| {
+            let mut builder = GitignoreBuilder::new(wallpaper_dir_path);
+            for pattern in patterns {
+                // Checked when parsing the options
+                builder.add_line(None, pattern).unwrap();
+            }
+            builder.build().unwrap()
+        };
+
+        Filter {
+            ignore_file,
+            exclude: patterns(exclude),
+            include: (!include.is_empty()).then(|| patterns(include)),
+        }
+    }
+
+    /// Why the file or directory at `path`, relative to the wallpaper directory, is skipped.
+    /// `None` if it isn't
+    pub fn check(&self, path: &str, is_dir: bool) -> Option<IgnoreReason> {
+        if let Match::Ignore(glob) = self.ignore_file.matched(path, is_dir) {
+            return Some(IgnoreReason::IgnoreFile(glob.original().to_owned()));
+        }
+        if let Match::Ignore(glob) = self.exclude.matched(path, is_dir) {
+            return Some(IgnoreReason::Excluded(glob.original().to_owned()));
+        }
+
+        // Directories are descended into as long as something below them may be included
+        match &self.include {
+            Some(include) if !is_dir => match include.matched_path_or_any_parents(path, false) {
+                Match::Ignore(_) => None,
+                _ => Some(IgnoreReason::NotIncluded),
+            },
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Image formats wallpapers can be in, recognised by the magic bytes at the start of the file.
+//! The extension only decides which files are looked at, TGA has no magic bytes and is taken on
+//! its extension alone.
+
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// Bytes read from the start of a file, enough for every signature below
+const HEADER_LEN: u64 = 16;
+
+/// Stored by name, `serde_binary` can't read back the unit variants it writes
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(into = "String", try_from = "String")]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Gif,
+    Pnm,
+    Tga,
+    Tiff,
+    WebP,
+    Bmp,
+    Farbfeld,
+}
+
+impl ImageFormat {
+    /// Format the extension stands for, ignoring case
+    pub fn from_extension(extension: &std::ffi::OsStr) -> Option<ImageFormat> {
+        match extension.to_str()?.to_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            "gif" => Some(ImageFormat::Gif),
+            "pnm" | "pbm" | "pgm" | "ppm" | "pam" => Some(ImageFormat::Pnm),
+            "tga" => Some(ImageFormat::Tga),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            "webp" => Some(ImageFormat::WebP),
+            "bmp" => Some(ImageFormat::Bmp),
+            "ff" | "farbfeld" => Some(ImageFormat::Farbfeld),
+            _ => None,
+        }
+    }
+
+    /// Format whose signature the header starts with
+    pub fn from_header(header: &[u8]) -> Option<ImageFormat> {
+        match header {
+            [0xff, 0xd8, 0xff, ..] => Some(ImageFormat::Jpeg),
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(ImageFormat::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(ImageFormat::Gif),
+            [b'P', b'1'..=b'7', b' ' | b'\t' | b'\r' | b'\n', ..] => Some(ImageFormat::Pnm),
+            [b'I', b'I', b'*', 0, ..] | [b'M', b'M', 0, b'*', ..] => Some(ImageFormat::Tiff),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+                Some(ImageFormat::WebP)
+            }
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            [b'f', b'a', b'r', b'b', b'f', b'e', b'l', b'd', ..] => Some(ImageFormat::Farbfeld),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::Png => "PNG",
+            ImageFormat::Gif => "GIF",
+            ImageFormat::Pnm => "PNM",
+            ImageFormat::Tga => "TGA",
+            ImageFormat::Tiff => "TIFF",
+            ImageFormat::WebP => "WebP",
+            ImageFormat::Bmp => "BMP",
+            ImageFormat::Farbfeld => "farbfeld",
+        }
+    }
+}
+
+impl From<ImageFormat> for String {
+    fn from(format: ImageFormat) -> Self {
+        format.name().to_owned()
+    }
+}
+
+impl TryFrom<String> for ImageFormat {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match name.as_str() {
+            "JPEG" => Ok(ImageFormat::Jpeg),
+            "PNG" => Ok(ImageFormat::Png),
+            "GIF" => Ok(ImageFormat::Gif),
+            "PNM" => Ok(ImageFormat::Pnm),
+            "TGA" => Ok(ImageFormat::Tga),
+            "TIFF" => Ok(ImageFormat::Tiff),
+            "WebP" => Ok(ImageFormat::WebP),
+            "BMP" => Ok(ImageFormat::Bmp),
+            "farbfeld" => Ok(ImageFormat::Farbfeld),
+            _ => Err(format!("unknown image format {name}")),
+        }
+    }
+}
+
+/// Format of the file going by its content, `None` if it isn't a recognised image
+pub fn sniff(path: &std::path::Path) -> Result<Option<ImageFormat>, std::io::Error> {
+    let mut header = vec![];
+    std::fs::File::open(path)?
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)?;
+
+    Ok(ImageFormat::from_header(&header))
+}
@@ -0,0 +1,266 @@
+//! Append-only log of every wallpaper that was set, one tab-separated entry per line:
+//! `timestamp backend monitor duration file_name`, times in Unix seconds.
+//!
+//! Like the state, every host appends to its own `history.<host>.log`, reading merges them all.
+
+use std::io::Write;
+
+pub const HISTORY_FILE_NAME: &str = "history.log";
+
+#[derive(Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub backend: String,
+    pub monitor: String,
+    /// Seconds the wallpaper actually stayed on screen, time spent suspended isn't counted. 0
+    /// while it is still shown, and for good if the daemon stopped before replacing it
+    pub duration: u64,
+    pub file_name: String,
+}
+
+impl HistoryEntry {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.timestamp, self.backend, self.monitor, self.duration, self.file_name
+        )
+    }
+
+    fn from_line(line: &str) -> Option<HistoryEntry> {
+        let mut fields = line.splitn(5, '\t');
+        Some(HistoryEntry {
+            timestamp: fields.next()?.parse().ok()?,
+            backend: fields.next()?.to_owned(),
+            monitor: fields.next()?.to_owned(),
+            duration: fields.next()?.parse().ok()?,
+            file_name: fields.next()?.to_owned(),
+        })
+    }
+}
+
+/// Which entries `read` keeps, `None` fields don't filter
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    pub last: Option<usize>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub file_name: Option<String>,
+}
+
+/// Path of the host's own history in the state directory
+pub fn host_path(state_dir: &std::path::Path, host_name: &str) -> std::path::PathBuf {
+    state_dir.join(format!("history.{host_name}.log"))
+}
+
+/// Histories of all hosts in the state directory, including the shared one of older releases
+fn paths(state_dir: &std::path::Path) -> Result<Vec<std::path::PathBuf>, std::io::Error> {
+    // The state directory is only created once something is written to it
+    let entries = match state_dir.read_dir() {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+    let mut paths: Vec<std::path::PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name == HISTORY_FILE_NAME
+                        || name.starts_with("history.") && name.ends_with(".log")
+                })
+        })
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Appends the entry, returning where it starts so that it can be amended
+pub fn append(history_path: &std::path::Path, entry: &HistoryEntry) -> Result<u64, std::io::Error> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_path)?;
+    let offset = file.metadata()?.len();
+    file.write_all(entry.to_line().as_bytes())?;
+    Ok(offset)
+}
+
+/// Rewrites the last entry of the host's history, starting at `offset`, e.g. with its duration
+/// once the wallpaper was replaced. Nothing else writes to the host's own history
+pub fn amend(
+    history_path: &std::path::Path,
+    offset: u64,
+    entry: &HistoryEntry,
+) -> Result<(), std::io::Error> {
+    use std::io::{Seek, SeekFrom};
+
+    let line = entry.to_line();
+    let mut file = std::fs::OpenOptions::new().write(true).open(history_path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(line.as_bytes())?;
+    file.set_len(offset + line.len() as u64)
+}
+
+/// Entries of all hosts in the state directories matching the filter, oldest first. Malformed
+/// lines are skipped
+pub fn read(
+    state_dirs: &[&std::path::Path],
+    filter: &HistoryFilter,
+) -> Result<Vec<HistoryEntry>, std::io::Error> {
+    let mut history = String::new();
+    for path in state_dirs
+        .iter()
+        .map(|state_dir| paths(state_dir))
+        .collect::<Result<Vec<_>, _>>()?
+        .concat()
+    {
+        match std::fs::read_to_string(path) {
+            Ok(host_history) => history.push_str(&host_history),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        if !history.ends_with('\n') {
+            history.push('\n');
+        }
+    }
+
+    let mut entries: Vec<HistoryEntry> = history
+        .lines()
+        .filter_map(HistoryEntry::from_line)
+        .filter(|entry| filter.since.is_none_or(|since| entry.timestamp >= since))
+        .filter(|entry| filter.until.is_none_or(|until| entry.timestamp < until))
+        .filter(|entry| {
+            filter
+                .file_name
+                .as_ref()
+                .is_none_or(|file_name| entry.file_name == *file_name)
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.timestamp);
+    if let Some(last) = filter.last {
+        entries.drain(..entries.len().saturating_sub(last));
+    }
+
+    Ok(entries)
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM:SS UTC`
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Formats a number of seconds as `1h 05m`, or `5m 03s` below an hour
+pub fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds / 60 % 60),
+    }
+}
+
+/// Parses `YYYY-MM-DD`, optionally followed by `THH:MM[:SS]` or ` HH:MM[:SS]`. The time is in
+/// UTC unless it ends with an offset from it, `Z`, `+HH:MM` or `-HH:MM`
+pub fn parse_timestamp(date: &str) -> Option<u64> {
+    let (date, time) = match date.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (date, None),
+    };
+    let (time, offset) = match time {
+        Some(time) => match time.find(['Z', '+', '-']) {
+            Some(at) => {
+                let (time, offset) = time.split_at(at);
+                (Some(time), parse_offset(offset)?)
+            }
+            None => (Some(time), 0),
+        },
+        None => (None, 0),
+    };
+
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let secs = match time {
+        Some(time) => {
+            let parts: Vec<u64> = time
+                .split(':')
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            match parts.as_slice() {
+                [hours, minutes] if *hours < 24 && *minutes < 60 => hours * 3600 + minutes * 60,
+                [hours, minutes, seconds] if *hours < 24 && *minutes < 60 && *seconds < 60 => {
+                    hours * 3600 + minutes * 60 + seconds
+                }
+                _ => return None,
+            }
+        }
+        None => 0,
+    };
+
+    let days = days_from_civil(year as i64, month, day);
+    u64::try_from(days * 86400 + secs as i64 - offset).ok()
+}
+
+/// Seconds ahead of UTC given as `Z`, `+HH:MM` or `-HH:MM`
+fn parse_offset(offset: &str) -> Option<i64> {
+    if offset == "Z" {
+        return Some(0);
+    }
+    let (sign, offset) = match offset.split_at_checked(1)? {
+        ("+", offset) => (1, offset),
+        ("-", offset) => (-1, offset),
+        _ => return None,
+    };
+    let (hours, minutes) = offset.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// Conversions between days since the Unix epoch and proleptic Gregorian dates, based on
+// http://howardhinnant.github.io/date_algorithms.html
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
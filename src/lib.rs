@@ -2,23 +2,110 @@
 #[cfg_attr(not(target_os = "windows"), path = "linux.rs")]
 pub mod wallpaper;
 
+pub mod collection;
+pub mod export;
+pub mod filter;
+pub mod format;
+pub mod history;
+pub mod merge;
+pub mod rotation;
+pub mod selection;
+pub mod source;
+pub mod state;
+
 impl Default for crate::wallpaper::WallSetter {
     fn default() -> Self {
         Self::new()
     }
 }
 
-use rand::prelude::*;
+use collection::{CollectionWeights, Shares};
+use filter::{Filter, IgnoreReason};
+use format::ImageFormat;
+use rotation::Rotation;
+use selection::{Balance, Constraints, Decay, SelectionStrategy, StrategyKind};
 use serde::{Deserialize, Serialize};
+use source::Source;
+use std::collections::{HashMap, HashSet};
 #[cfg(target_os = "linux")]
 use wallpaper::WallSetterProgram;
 
-const COUNT_FACTOR: f64 = 1.001;
-
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Wallpaper {
     pub file_name: String,
     pub count: usize,
+    /// Still waiting to be shown in the current shuffle-bag cycle
+    pub in_bag: bool,
+    /// Unix timestamp in seconds of the last time the wallpaper was picked
+    pub last_shown: std::option::Option<u64>,
+    /// User rating from 1 to 5 stars, every star above or below `DEFAULT_RATING` doubles or halves the odds
+    pub rating: u8,
+    /// Excluded from rotation while still being tracked
+    pub banned: bool,
+    /// Number of rotations since the wallpaper was last picked
+    pub rotations_since_shown: usize,
+    /// Hash of the file content, identifies the wallpaper across renames and moves. Only the
+    /// edges of the file are hashed, see `hash_file`
+    pub hash: std::option::Option<u64>,
+    /// File size in bytes when `hash` was computed
+    pub size: u64,
+    /// Modification time as a Unix timestamp in seconds when `hash` was computed
+    pub mtime: u64,
+    /// Format the file was taken as when `size` and `mtime` were recorded, so that unchanged
+    /// files aren't read again on every scan. `None` until it is first scanned
+    pub format: std::option::Option<ImageFormat>,
+    /// Seconds the wallpaper actually stayed on screen, time spent suspended is not counted
+    pub seconds_shown: u64,
+    /// Unix timestamp in seconds of the last change to `rating` or `banned`, 0 if never changed
+    pub edited: u64,
+    /// Unix timestamp in seconds of when the file went missing, the record is kept for a while in
+    /// case the file comes back
+    pub removed: std::option::Option<u64>,
+}
+
+pub const DEFAULT_RATING: u8 = 3;
+pub const MAX_RATING: u8 = 5;
+
+impl Wallpaper {
+    pub fn new(file_name: String) -> Wallpaper {
+        Wallpaper {
+            file_name,
+            count: 0,
+            in_bag: true,
+            last_shown: None,
+            rating: DEFAULT_RATING,
+            banned: false,
+            rotations_since_shown: 0,
+            hash: None,
+            size: 0,
+            mtime: 0,
+            format: None,
+            seconds_shown: 0,
+            edited: 0,
+            removed: None,
+        }
+    }
+
+    /// Whether the wallpaper has nothing recorded but its file, as when it was just added
+    pub fn is_fresh(&self) -> bool {
+        self.count == 0 && self.last_shown.is_none() && self.seconds_shown == 0 && self.edited == 0
+    }
+
+    /// Recomputes the content hash if the file changed since it was last hashed
+    fn refresh_fingerprint(&mut self, wallpaper_dir_path: &std::path::Path) {
+        let path = wallpaper_dir_path.join(&self.file_name);
+        let Ok(metadata) = path.metadata() else {
+            return;
+        };
+        let size = metadata.len();
+        let mtime = modified_secs(&metadata);
+
+        if self.hash.is_none() || self.size != size || self.mtime != mtime {
+            self.hash = hash_file(&path, size).ok();
+            self.size = size;
+            self.mtime = mtime;
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -27,12 +114,47 @@ pub enum Option {
     PrintState,
     PrintHelp,
     Interval(u64),
+    Strategy(StrategyKind),
+    Seed(u64),
+    Recency(u64),
+    Rate(Target, u8),
+    Ban(Target),
+    Unban(Target),
+    NoRepeat(usize),
+    Fairness(usize),
+    Decay(Decay),
+    Balance(Balance),
+    KeepRemoved(u64),
+    Recursive(usize),
+    Collections(Vec<String>),
+    ExcludeCollections(Vec<String>),
+    CollectionWeights(CollectionWeights),
+    Include(String),
+    Exclude(String),
+    Simulate,
+    Config(std::path::PathBuf),
+    ExportState(std::path::PathBuf),
+    StateInDir,
+    Host(String),
+    History,
+    HistoryLast(usize),
+    HistorySince(u64),
+    HistoryUntil(u64),
+    HistoryFile(String),
+    ImportState(std::path::PathBuf),
     #[cfg(target_os = "linux")]
     Program(WallSetterProgram),
     #[cfg(target_os = "windows")]
     HideTerminalWindow,
 }
 
+/// Wallpaper targeted by a one-shot command
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Current,
+    File(String),
+}
+
 #[derive(Debug)]
 pub enum Error {
     InvalidOption(String),
@@ -44,223 +166,840 @@ pub fn process_args() -> Result<Vec<Option>, Error> {
     let mut options = vec![];
     let mut args: Vec<String> = std::env::args().skip(1).collect();
 
-    let last_arg = args.pop().ok_or(Error::InvalidOptionsStructure)?;
-    if last_arg != "--help" {
-        let wallpapers_dir_path = last_arg;
+    // Directories come after the options
+    let first_dir_arg = args
+        .iter()
+        .rposition(|arg| arg.starts_with("--"))
+        .map_or(0, |index| index + 1);
+    for wallpapers_dir_path in args.split_off(first_dir_arg) {
         let wallpapers_dir_path = std::path::PathBuf::from(wallpapers_dir_path);
         if !wallpapers_dir_path.is_dir() {
             return Err(Error::InvalidOptionsStructure);
         }
         options.push(Option::Path(wallpapers_dir_path));
-    } else {
-        args.push(last_arg);
     }
 
     for arg in args {
-        let arg = match arg.as_str() {
-            "--print-state" => Ok(Option::PrintState),
-            "--help" => Ok(Option::PrintHelp),
-            s if s.starts_with("--interval=") => {
-                if let Some(Ok(min)) = s.split_once('=').map(|(_, s)| s.parse::<u64>()) {
-                    if min > 0 {
-                        Ok(Option::Interval(min))
-                    } else {
-                        Err(Error::InvalidOption(arg))
-                    }
+        options.push(parse_option(arg)?);
+    }
+    let dirs_given = options
+        .iter()
+        .any(|option| matches!(option, Option::Path(_)));
+
+    // Options from the config file come after the command line ones so the latter take precedence
+    let config_path = options.iter().find_map(|option| match option {
+        Option::Config(path) => Some(path.clone()),
+        _ => None,
+    });
+    let config_args = match config_path {
+        Some(path) => read_config(&path)
+            .ok_or(Error::InvalidOption(format!("--config={}", path.display())))?,
+        None => default_config_path()
+            .and_then(|path| read_config(&path))
+            .unwrap_or_default(),
+    };
+    for arg in config_args {
+        // Directories on the command line replace the ones from the config file
+        match parse_option(arg)? {
+            Option::Path(_) if dirs_given => {}
+            option => options.push(option),
+        }
+    }
+
+    if !options.contains(&Option::PrintHelp)
+        && !options
+            .iter()
+            .any(|option| matches!(option, Option::Path(_)))
+    {
+        return Err(Error::InvalidOptionsStructure);
+    }
+
+    Ok(options)
+}
+
+fn parse_option(arg: String) -> Result<Option, Error> {
+    match arg.as_str() {
+        "--print-state" => Ok(Option::PrintState),
+        "--simulate" => Ok(Option::Simulate),
+        "--state-in-dir" => Ok(Option::StateInDir),
+        s if s.starts_with("--host=") => {
+            let host_name = &s["--host=".len()..];
+            let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if !host_name.is_empty() && host_name.chars().all(valid) {
+                Ok(Option::Host(host_name.to_owned()))
+            } else {
+                Err(Error::InvalidOption(arg))
+            }
+        }
+        "--history" => Ok(Option::History),
+        s if s.starts_with("--history-last=") => s
+            .split_once('=')
+            .and_then(|(_, s)| s.parse::<usize>().ok())
+            .map(Option::HistoryLast)
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--history-since=") => s
+            .split_once('=')
+            .and_then(|(_, s)| history::parse_timestamp(s))
+            .map(Option::HistorySince)
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--history-until=") => s
+            .split_once('=')
+            .and_then(|(_, s)| history::parse_timestamp(s))
+            .map(Option::HistoryUntil)
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--history-file=") => {
+            Ok(Option::HistoryFile(s["--history-file=".len()..].to_owned()))
+        }
+        s if s.starts_with("--export-state=") || s.starts_with("--import-state=") => {
+            let (name, path) = s.split_once('=').unwrap();
+            let path = std::path::PathBuf::from(path);
+            if export::StateFormat::from_path(&path).is_none() {
+                Err(Error::InvalidOption(arg))
+            } else if name == "--export-state" {
+                Ok(Option::ExportState(path))
+            } else {
+                Ok(Option::ImportState(path))
+            }
+        }
+        s if s.starts_with("--config=") => Ok(Option::Config(std::path::PathBuf::from(&s[9..]))),
+        s if s.starts_with("--balance=") => s
+            .split_once('=')
+            .and_then(|(_, s)| Balance::from_name(s))
+            .map(Option::Balance)
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--dir=") => {
+            let wallpapers_dir_path = std::path::PathBuf::from(&s["--dir=".len()..]);
+            if wallpapers_dir_path.is_dir() {
+                Ok(Option::Path(wallpapers_dir_path))
+            } else {
+                Err(Error::InvalidOption(arg))
+            }
+        }
+        "--recursive" => Ok(Option::Recursive(usize::MAX)),
+        s if s.starts_with("--recursive=") => s
+            .split_once('=')
+            .and_then(|(_, s)| s.parse::<usize>().ok())
+            .map(Option::Recursive)
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--collections=") || s.starts_with("--exclude-collections=") => {
+            let (name, collections) = s.split_once('=').unwrap();
+            let collections: Vec<String> = collections
+                .split(',')
+                .filter(|collection| !collection.is_empty())
+                .map(|collection| collection.to_owned())
+                .collect();
+            if collections.is_empty() {
+                Err(Error::InvalidOption(arg))
+            } else if name == "--collections" {
+                Ok(Option::Collections(collections))
+            } else {
+                Ok(Option::ExcludeCollections(collections))
+            }
+        }
+        s if s.starts_with("--collection-weights=") => s
+            .split_once('=')
+            .and_then(|(_, s)| {
+                s.split(',')
+                    .map(|weight| {
+                        let (name, weight) = weight.rsplit_once(':')?;
+                        let weight = weight.parse::<f64>().ok()?;
+                        (!name.is_empty() && weight.is_finite() && weight > 0.0)
+                            .then(|| (name.to_owned(), weight))
+                    })
+                    .collect::<std::option::Option<Vec<_>>>()
+            })
+            .map(|weights| Option::CollectionWeights(CollectionWeights { weights }))
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--include=") || s.starts_with("--exclude=") => {
+            let (name, pattern) = s.split_once('=').unwrap();
+            if pattern.is_empty() || !filter::is_valid_pattern(pattern) {
+                Err(Error::InvalidOption(arg))
+            } else if name == "--include" {
+                Ok(Option::Include(pattern.to_owned()))
+            } else {
+                Ok(Option::Exclude(pattern.to_owned()))
+            }
+        }
+        s if s.starts_with("--keep-removed=") => {
+            match s.split_once('=').map(|(_, s)| s.parse::<u64>()) {
+                // Used in seconds, which has to fit as well
+                Some(Ok(days)) if days.checked_mul(24 * 60 * 60).is_some() => {
+                    Ok(Option::KeepRemoved(days))
+                }
+                _ => Err(Error::InvalidOption(arg)),
+            }
+        }
+        s if s.starts_with("--decay=") => s
+            .split_once('=')
+            .and_then(|(_, s)| Decay::from_arg(s))
+            .map(Option::Decay)
+            .ok_or(Error::InvalidOption(arg)),
+        "--help" => Ok(Option::PrintHelp),
+        s if s.starts_with("--interval=") => {
+            if let Some(Ok(min)) = s.split_once('=').map(|(_, s)| s.parse::<u64>()) {
+                if min > 0 {
+                    Ok(Option::Interval(min))
                 } else {
                     Err(Error::InvalidOption(arg))
                 }
+            } else {
+                Err(Error::InvalidOption(arg))
             }
-            #[cfg(target_os = "linux")]
-            s if s.starts_with("--program=") => {
-                if s.ends_with("swww") {
-                    Ok(Option::Program(WallSetterProgram::SWWW))
-                } else if s.ends_with("plasma-apply-wallpaperimage") {
-                    if let Ok(val) = std::env::var("XDG_CURRENT_DESKTOP") {
-                        if val == "KDE" {
-                            Ok(Option::Program(WallSetterProgram::PLASMA))
-                        } else {
-                            Err(Error::UnavailableOption(
-                                "plasma-apply-wallpaperimage".to_owned(),
-                            ))
-                        }
+        }
+        s if s.starts_with("--seed=") => s
+            .split_once('=')
+            .and_then(|(_, s)| s.parse::<u64>().ok())
+            .map(Option::Seed)
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--recency=") => {
+            match s.split_once('=').map(|(_, s)| s.parse::<u64>()) {
+                // Used in seconds, which has to fit as well
+                Some(Ok(hours)) if hours > 0 && hours.checked_mul(60 * 60).is_some() => {
+                    Ok(Option::Recency(hours))
+                }
+                _ => Err(Error::InvalidOption(arg)),
+            }
+        }
+        s if s.starts_with("--rate=") => {
+            let value = s.split_once('=').map(|(_, s)| s).unwrap_or("");
+            let (target, rating) = match value.rsplit_once(':') {
+                Some((file, rating)) => (Target::File(file.to_owned()), rating),
+                None => (Target::Current, value),
+            };
+            match rating.parse::<u8>() {
+                Ok(rating) if (1..=MAX_RATING).contains(&rating) => {
+                    Ok(Option::Rate(target, rating))
+                }
+                _ => Err(Error::InvalidOption(arg)),
+            }
+        }
+        "--ban" => Ok(Option::Ban(Target::Current)),
+        "--unban" => Ok(Option::Unban(Target::Current)),
+        s if s.starts_with("--ban=") => Ok(Option::Ban(Target::File(s[6..].to_owned()))),
+        s if s.starts_with("--unban=") => Ok(Option::Unban(Target::File(s[8..].to_owned()))),
+        s if s.starts_with("--no-repeat=") => s
+            .split_once('=')
+            .and_then(|(_, s)| s.parse::<usize>().ok())
+            .map(Option::NoRepeat)
+            .ok_or(Error::InvalidOption(arg)),
+        s if s.starts_with("--fairness=") => {
+            match s.split_once('=').map(|(_, s)| s.parse::<usize>()) {
+                Some(Ok(rotations)) if rotations > 0 => Ok(Option::Fairness(rotations)),
+                _ => Err(Error::InvalidOption(arg)),
+            }
+        }
+        s if s.starts_with("--strategy=") => s
+            .split_once('=')
+            .and_then(|(_, s)| StrategyKind::from_name(s))
+            .map(Option::Strategy)
+            .ok_or(Error::InvalidOption(arg)),
+        #[cfg(target_os = "linux")]
+        s if s.starts_with("--program=") => {
+            if s.ends_with("swww") {
+                Ok(Option::Program(WallSetterProgram::SWWW))
+            } else if s.ends_with("plasma-apply-wallpaperimage") {
+                if let Ok(val) = std::env::var("XDG_CURRENT_DESKTOP") {
+                    if val == "KDE" {
+                        Ok(Option::Program(WallSetterProgram::PLASMA))
                     } else {
                         Err(Error::UnavailableOption(
                             "plasma-apply-wallpaperimage".to_owned(),
                         ))
                     }
-                } else if s.ends_with("hyprpaper") {
-                    #[allow(unused_mut, unused_assignments)]
-                    let mut option = Err(Error::InvalidOption(arg));
-                    #[cfg(all(feature = "hyprpaper", target_os = "linux"))]
-                    {
-                        if let Ok(_) = std::env::var("HYPRLAND_INSTANCE_SIGNATURE") {
-                            option = Ok(Option::Program(WallSetterProgram::HYPRPAPER));
-                        } else {
-                            option = Err(Error::UnavailableOption("hyprpaper".to_owned()))
-                        }
-                    }
-                    option
                 } else {
-                    Err(Error::InvalidOption(arg))
+                    Err(Error::UnavailableOption(
+                        "plasma-apply-wallpaperimage".to_owned(),
+                    ))
+                }
+            } else if s.ends_with("hyprpaper") {
+                #[allow(unused_mut, unused_assignments)]
+                let mut option = Err(Error::InvalidOption(arg));
+                #[cfg(all(feature = "hyprpaper", target_os = "linux"))]
+                {
+                    if std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
+                        option = Ok(Option::Program(WallSetterProgram::HYPRPAPER));
+                    } else {
+                        option = Err(Error::UnavailableOption("hyprpaper".to_owned()))
+                    }
                 }
+                option
+            } else {
+                Err(Error::InvalidOption(arg))
             }
-            #[cfg(target_os = "windows")]
-            "--hidden" => Ok(Option::HideTerminalWindow),
-            _ => Err(Error::InvalidOption(arg)),
-        };
-        options.push(arg?);
+        }
+        #[cfg(target_os = "windows")]
+        "--hidden" => Ok(Option::HideTerminalWindow),
+        _ => Err(Error::InvalidOption(arg)),
     }
+}
 
-    Ok(options)
+/// Reads options from a config file, one per line and written like on the command line with
+/// the leading `--` being optional. Empty lines and lines starting with `#` are skipped
+pub fn read_config(path: &std::path::Path) -> std::option::Option<Vec<String>> {
+    let config = std::fs::read_to_string(path).ok()?;
+    let args = config
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            if line.starts_with("--") {
+                line.to_owned()
+            } else {
+                format!("--{line}")
+            }
+        });
+
+    Some(args.collect())
+}
+
+pub fn default_config_path() -> std::option::Option<std::path::PathBuf> {
+    #[cfg(target_os = "windows")]
+    let config_dir = std::env::var_os("APPDATA").map(std::path::PathBuf::from);
+    #[cfg(not(target_os = "windows"))]
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config"))
+        });
+
+    config_dir.map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("config"))
 }
 
 pub fn print_help() {
-    println!("Usage: {} [OPTIONS] DIRECTORY", env!("CARGO_PKG_NAME"));
-    println!("       {} --print-state DIRECTORY", env!("CARGO_PKG_NAME"));
+    println!("Usage: {} [OPTIONS] DIRECTORY...", env!("CARGO_PKG_NAME"));
+    println!(
+        "       {} --print-state DIRECTORY...",
+        env!("CARGO_PKG_NAME")
+    );
+    println!(
+        "       {} --simulate [OPTIONS] DIRECTORY...",
+        env!("CARGO_PKG_NAME")
+    );
     println!("       {} --help", env!("CARGO_PKG_NAME"));
     println!("Options:");
     println!("\t --help");
+    println!("\t --dir=<path>");
     println!("\t --interval=<u64>");
+    println!("\t --strategy=<decay|uniform|shuffle|sequential>");
+    println!("\t --seed=<u64>");
+    println!("\t --decay=<exp:<base>|linear|power:<exponent>|softmax:<temperature>>");
+    println!("\t --balance=<count|time>");
+    println!("\t --recency=<hours>");
+    println!("\t --no-repeat=<usize>");
+    println!("\t --fairness=<rotations>");
+    println!("\t --rate=[<file>:]<1-5>");
+    println!("\t --ban[=<file>]");
+    println!("\t --unban[=<file>]");
+    println!("\t --keep-removed=<days>");
+    println!("\t --recursive[=<depth>]");
+    println!("\t --collections=<collection>[,<collection>...]");
+    println!("\t --exclude-collections=<collection>[,<collection>...]");
+    println!("\t --collection-weights=<collection|*>:<weight>[,...]");
+    println!("\t --include=<pattern>");
+    println!("\t --exclude=<pattern>");
+    println!("\t --config=<path>");
+    println!("\t --state-in-dir");
+    println!("\t --host=<name>");
     #[cfg(target_os = "windows")]
     println!("\t --hidden");
     #[cfg(not(all(feature = "hyprpaper", target_os = "linux")))]
     println!("\t --program=<swww|plasma-apply-wallpaperimage>");
     #[cfg(all(feature = "hyprpaper", target_os = "linux"))]
     println!("\t --program=<swww|hyprpaper|plasma-apply-wallpaperimage>");
+    println!("History options:");
+    println!("\t --history-last=<usize>");
+    println!("\t --history-since=<YYYY-MM-DD[THH:MM[:SS][Z|+HH:MM|-HH:MM]]>");
+    println!("\t --history-until=<YYYY-MM-DD[THH:MM[:SS][Z|+HH:MM|-HH:MM]]>");
+    println!("\t Times are in UTC unless an offset from it is given");
+    println!("\t --history-file=<file>");
 }
 
+/// Index of the wallpaper to show next, recording that it was shown. This builds a whole
+/// `Rotation` for a single pick, to pick repeatedly keep one and call `Rotation::pick`
 pub fn pick_random_wallpaper(
-    wallpaper_dir_path: &std::path::Path,
     wallpapers: &mut [Wallpaper],
-) -> std::path::PathBuf {
-    let total_count_w: f64 = wallpapers
-        .iter()
-        .map(|wallpaper| wallpaper.count as f64)
-        .fold(0.0, |acc, count: f64| acc + COUNT_FACTOR.powf(-count));
-
-    let rand_num = get_random_num(total_count_w);
-    let mut cum_count_w: f64 = 0.0;
-    let wallpaper = wallpapers
-        .iter_mut()
-        .find(|wallpaper| {
-            cum_count_w += COUNT_FACTOR.powf(-(wallpaper.count as f64));
-            cum_count_w >= rand_num
-        })
-        .unwrap();
-
-    wallpaper.count += 1;
-
-    wallpaper_dir_path.join(wallpaper.file_name.clone())
+    strategy: &mut dyn SelectionStrategy,
+    constraints: &Constraints,
+    shares: &Shares,
+    rng: &mut dyn rand::RngCore,
+) -> std::option::Option<usize> {
+    let mut rotation = Rotation::new(wallpapers, strategy, constraints, shares);
+    let index = rotation.pick(wallpapers, strategy, rng);
+    rotation.finish(wallpapers);
+    index
 }
 
+/// Seconds a removed wallpaper is remembered by default, in case its file comes back
+pub const DEFAULT_REMOVED_RETENTION: u64 = 30 * 24 * 60 * 60;
+
+/// Brings the records in line with the directory. Records of removed files are kept for
+/// `removed_retention` seconds and restored if a file with the same name or content shows up
 pub fn sync_wallpapers(
     wallpaper_dir_path: &std::path::Path,
-    mut wallpapers: Vec<Wallpaper>,
+    wallpapers: Vec<Wallpaper>,
+    scan: &Scan,
+    removed_retention: u64,
 ) -> Vec<Wallpaper> {
-    let wallpapers_names = get_wallpapers_paths_from_path(wallpaper_dir_path);
+    sync_wallpapers_with_report(wallpaper_dir_path, wallpapers, scan, removed_retention).0
+}
 
-    let old_wallpapers_names: Vec<&String> = wallpapers
+/// `sync_wallpapers`, along with what the scan of the directory left out or found odd
+pub fn sync_wallpapers_with_report(
+    wallpaper_dir_path: &std::path::Path,
+    wallpapers: Vec<Wallpaper>,
+    scan: &Scan,
+    removed_retention: u64,
+) -> (Vec<Wallpaper>, ScanReport) {
+    let DirScan {
+        wallpapers: scanned,
+        report,
+        ..
+    } = scan_wallpaper_dir(wallpaper_dir_path, scan, &wallpapers);
+    let formats: HashMap<&str, ImageFormat> = scanned
         .iter()
-        .map(|wallpaper_name| &wallpaper_name.file_name)
+        .map(|(name, format)| (name.as_str(), *format))
         .collect();
 
-    let mut new_wallpapers: Vec<Wallpaper> = wallpapers_names
+    let old_wallpapers_names: HashSet<&str> = wallpapers
         .iter()
-        .filter(|wallpaper_name| !old_wallpapers_names.contains(wallpaper_name))
-        .map(|wallpaper_name| Wallpaper {
-            file_name: wallpaper_name.clone(),
-            count: 0,
-        })
+        .map(|wallpaper| wallpaper.file_name.as_str())
         .collect();
-    new_wallpapers
-        .iter()
-        .for_each(|wallpaper| println!("Pushing {}", wallpaper.file_name));
 
-    let removed_wallpapers_names: Vec<String> = old_wallpapers_names
+    let new_wallpapers: Vec<Wallpaper> = scanned
         .iter()
-        .filter(|wallpaper_name| !wallpapers_names.contains(wallpaper_name))
-        .map(|wallpaper_name| wallpaper_name.to_string())
+        .filter(|(wallpaper_name, _)| !old_wallpapers_names.contains(wallpaper_name.as_str()))
+        .map(|(wallpaper_name, format)| {
+            let mut wallpaper = Wallpaper::new(wallpaper_name.clone());
+            wallpaper.refresh_fingerprint(wallpaper_dir_path);
+            wallpaper.format = Some(*format);
+            wallpaper
+        })
         .collect();
 
-    for to_remove in removed_wallpapers_names {
-        if let Some(to_remove_index) = wallpapers
-            .iter()
-            .position(|wallpaper| wallpaper.file_name == to_remove)
-        {
-            println!("Popping {}", to_remove);
-            wallpapers.swap_remove(to_remove_index);
+    let (mut wallpapers, removed_wallpapers): (Vec<Wallpaper>, Vec<Wallpaper>) = wallpapers
+        .into_iter()
+        .partition(|wallpaper| formats.contains_key(wallpaper.file_name.as_str()));
+    for wallpaper in wallpapers
+        .iter_mut()
+        .filter(|wallpaper| wallpaper.removed.is_some())
+    {
+        println!("Restoring {}", wallpaper.file_name);
+        wallpaper.removed = None;
+    }
+    for wallpaper in wallpapers.iter_mut() {
+        wallpaper.refresh_fingerprint(wallpaper_dir_path);
+        wallpaper.format = formats.get(wallpaper.file_name.as_str()).copied();
+    }
+
+    // A removed wallpaper with the same content as a new one was renamed or moved, it keeps its history
+    let mut removed_by_hash: HashMap<u64, Vec<Wallpaper>> = HashMap::new();
+    let mut removed_unhashed = vec![];
+    for wallpaper in removed_wallpapers {
+        match wallpaper.hash {
+            Some(hash) => removed_by_hash.entry(hash).or_default().push(wallpaper),
+            None => removed_unhashed.push(wallpaper),
+        }
+    }
+    // Files that just went missing are matched before those removed earlier
+    removed_by_hash
+        .values_mut()
+        .for_each(|removed| removed.sort_by_key(|wallpaper| wallpaper.removed.is_none()));
+
+    for new_wallpaper in new_wallpapers {
+        let renamed = new_wallpaper
+            .hash
+            .and_then(|hash| removed_by_hash.get_mut(&hash))
+            .and_then(|removed| removed.pop());
+        match renamed {
+            Some(mut wallpaper) => {
+                match wallpaper.removed.take() {
+                    Some(_) => println!(
+                        "Restoring {} as {}",
+                        wallpaper.file_name, new_wallpaper.file_name
+                    ),
+                    None => println!(
+                        "Renaming {} to {}",
+                        wallpaper.file_name, new_wallpaper.file_name
+                    ),
+                }
+                wallpaper.file_name = new_wallpaper.file_name;
+                wallpaper.size = new_wallpaper.size;
+                wallpaper.mtime = new_wallpaper.mtime;
+                wallpaper.format = new_wallpaper.format;
+                wallpapers.push(wallpaper);
+            }
+            None => {
+                println!("Pushing {}", new_wallpaper.file_name);
+                wallpapers.push(new_wallpaper);
+            }
         }
     }
 
-    wallpapers.append(&mut new_wallpapers);
+    let now = unix_now();
+    for mut wallpaper in removed_by_hash
+        .into_values()
+        .flatten()
+        .chain(removed_unhashed)
+    {
+        let removed = match wallpaper.removed {
+            Some(removed) => removed,
+            None => {
+                println!("Popping {}", wallpaper.file_name);
+                *wallpaper.removed.insert(now)
+            }
+        };
+        if now.saturating_sub(removed) < removed_retention {
+            wallpapers.push(wallpaper);
+        } else if removed != now {
+            println!("Forgetting {}", wallpaper.file_name);
+        }
+    }
 
-    wallpapers
+    (wallpapers, report)
 }
 
 pub fn mean_centering_counts(mut wallpapers: Vec<Wallpaper>) -> Vec<Wallpaper> {
-    if let Some(min) = wallpapers.iter().map(|w| w.count).min() {
+    // Removed wallpapers are shifted along so they come back level with the others
+    let min = wallpapers
+        .iter()
+        .filter(|w| w.removed.is_none())
+        .map(|w| w.count)
+        .min();
+    if let Some(min) = min {
         if min != 0 {
-            wallpapers.iter_mut().for_each(|w| w.count -= min);
+            wallpapers
+                .iter_mut()
+                .for_each(|w| w.count = w.count.saturating_sub(min));
         }
     }
     wallpapers
 }
 
-pub fn find_wallpaper_path(options: &Vec<Option>) -> std::option::Option<&std::path::PathBuf> {
-    let wallpapers_dir_path = options.iter().find_map(|option| match option {
+/// Index of the wallpaper a command refers to, the current one is the most recently shown
+pub fn find_target(wallpapers: &[Wallpaper], target: &Target) -> std::option::Option<usize> {
+    match target {
+        Target::Current => wallpapers
+            .iter()
+            .enumerate()
+            .filter(|(_, wallpaper)| wallpaper.removed.is_none() && wallpaper.last_shown.is_some())
+            .max_by_key(|(_, wallpaper)| wallpaper.last_shown)
+            .map(|(index, _)| index),
+        Target::File(file_name) => wallpapers
+            .iter()
+            .position(|wallpaper| wallpaper.removed.is_none() && wallpaper.file_name == *file_name),
+    }
+}
+
+pub fn find_wallpaper_paths(options: &[Option]) -> Vec<&std::path::PathBuf> {
+    let wallpapers_dir_paths = options.iter().filter_map(|option| match option {
         Option::Path(path) => Some(path),
         _ => None,
     });
 
-    wallpapers_dir_path
+    wallpapers_dir_paths.collect()
 }
 
-pub fn retrieve_wallpapers(path: &std::path::PathBuf) -> Vec<Wallpaper> {
-    let mut wallpapers_state_path = path.clone();
-    wallpapers_state_path.push("state.bin");
-    let wallpapers: Vec<Wallpaper> = if let Ok(state) = std::fs::read(&wallpapers_state_path) {
+/// Wallpaper directories given in the options with the path of the state of each, a directory
+/// given twice is only listed once
+pub fn find_sources(options: &[Option]) -> Result<Vec<Source>, std::io::Error> {
+    let host_name = find_host_name(options);
+    let mut sources: Vec<Source> = vec![];
+    for wallpapers_dir_path in find_wallpaper_paths(options) {
+        let in_dir_state_path = state::host_path(
+            &wallpapers_dir_path.join(state::STATE_FILE_NAME),
+            &host_name,
+        );
+        let state_path = if options.contains(&Option::StateInDir) {
+            in_dir_state_path
+        } else {
+            let in_dir_peers = state::peer_paths(&in_dir_state_path);
+            if !in_dir_peers.is_empty() {
+                let host_names: Vec<String> =
+                    in_dir_peers.into_iter().map(|(name, _)| name).collect();
+                eprintln!(
+                    "Warning: {} holds the state of {}, pass --state-in-dir to share this host's \
+                     state with them",
+                    wallpapers_dir_path.display(),
+                    host_names.join(", ")
+                );
+            }
+            state::host_path(&state::locate(wallpapers_dir_path)?, &host_name)
+        };
+
+        if sources.iter().all(|source| source.state_path != state_path) {
+            sources.push(Source {
+                dir_path: wallpapers_dir_path.clone(),
+                state_path,
+            });
+        }
+    }
+
+    Ok(sources)
+}
+
+/// Name the state of this machine is kept under, the detected host name unless overridden
+pub fn find_host_name(options: &[Option]) -> String {
+    options
+        .iter()
+        .find_map(|option| match option {
+            Option::Host(host_name) => Some(host_name.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(state::host_name)
+}
+
+pub fn retrieve_wallpapers(
+    path: &std::path::Path,
+    wallpapers_state_path: &std::path::Path,
+    scan: &Scan,
+) -> Vec<Wallpaper> {
+    let wallpapers_state_path = state::readable_path(path, wallpapers_state_path);
+    let wallpapers: Vec<Wallpaper> = if let Some(wallpapers) = state::read(&wallpapers_state_path) {
         println!("Using previous state");
-        serde_binary::from_vec(state, serde_binary::binary_stream::Endian::Little).unwrap()
+        wallpapers
     } else {
-        let wallpapers_paths = get_wallpapers_paths_from_path(path);
-        let wallpapers = wallpapers_paths
-            .into_iter()
-            .map(|wallpaper_path| Wallpaper {
-                file_name: wallpaper_path,
-                count: 0,
-            });
+        if wallpapers_state_path.exists() {
+            eprintln!("Warning: no usable state left, rescanning the wallpaper directory");
+        }
+        let wallpapers_paths = get_wallpapers_paths_from_path(path, scan);
+        let wallpapers = wallpapers_paths.into_iter().map(Wallpaper::new);
         wallpapers.collect()
     };
 
     wallpapers
 }
 
-pub fn get_wallpapers_paths_from_path(wallpaper_dir_path: &std::path::Path) -> Vec<String> {
-    let wallpapers = wallpaper_dir_path.read_dir().unwrap();
-    let wallpapers = wallpapers.filter_map(|dir_entry| dir_entry.ok());
-    let wallpapers =
-        wallpapers.filter(|dir_entry| dir_entry.path().extension().is_some_and(is_img_file));
-    let wallpapers = wallpapers.map(|dir_entry| {
-        dir_entry
-            .file_name()
-            .into_string()
-            .unwrap_or_else(|_| panic!("Invalid Unicode file name: {:?}", dir_entry))
-    });
+pub fn save_wallpapers(
+    wallpapers_state_path: &std::path::Path,
+    wallpapers: &[Wallpaper],
+) -> Result<(), std::io::Error> {
+    state::write(wallpapers_state_path, wallpapers)
+}
+
+/// How the wallpaper directory is scanned
+#[derive(Debug, Default, Clone)]
+pub struct Scan {
+    /// Levels of subdirectories descended into, 0 only lists the directory itself
+    pub max_depth: usize,
+    /// Patterns of the files scanned, every file if empty
+    pub include: Vec<String>,
+    /// Patterns of the files and directories skipped
+    pub exclude: Vec<String>,
+}
+
+/// Paths of the wallpapers relative to the directory, with `/` as separator on every platform
+pub fn get_wallpapers_paths_from_path(
+    wallpaper_dir_path: &std::path::Path,
+    scan: &Scan,
+) -> Vec<String> {
+    scan_wallpaper_dir(wallpaper_dir_path, scan, &[])
+        .wallpapers
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// What a scan left out or found odd, paths are relative to the directory
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    /// Images and directories that were skipped, with the reason. Files below a skipped
+    /// directory aren't listed
+    pub ignored: Vec<(String, IgnoreReason)>,
+    /// Images whose content doesn't match their extension, with the format of each
+    pub mismatched: Vec<(String, ImageFormat, ImageFormat)>,
+    /// TGA images, which have no signature and are taken on their extension alone
+    pub unverified: Vec<String>,
+}
+
+/// Scans the directory, the formats recorded in `known` are reused for the files that didn't
+/// change since
+fn scan_wallpaper_dir<'a>(
+    wallpaper_dir_path: &std::path::Path,
+    scan: &Scan,
+    known: &'a [Wallpaper],
+) -> DirScan<'a> {
+    let mut dir_scan = DirScan {
+        filter: Filter::new(wallpaper_dir_path, &scan.include, &scan.exclude),
+        known: known
+            .iter()
+            .map(|wallpaper| (wallpaper.file_name.as_str(), wallpaper))
+            .collect(),
+        wallpapers: vec![],
+        report: ScanReport::default(),
+    };
+    let dir_entries = wallpaper_dir_path.read_dir().unwrap();
+    dir_scan.scan_dir_entries(dir_entries, "", scan.max_depth);
+
+    dir_scan
+}
+
+struct DirScan<'a> {
+    filter: Filter,
+    known: HashMap<&'a str, &'a Wallpaper>,
+    /// Images found, with the format each was taken as
+    wallpapers: Vec<(String, ImageFormat)>,
+    report: ScanReport,
+}
+
+impl DirScan<'_> {
+    /// Format recorded for the file if it didn't change since
+    fn known_format(
+        &self,
+        path: &str,
+        metadata: &std::fs::Metadata,
+    ) -> std::option::Option<ImageFormat> {
+        let wallpaper = self.known.get(path)?;
+        if wallpaper.size != metadata.len() || wallpaper.mtime != modified_secs(metadata) {
+            return None;
+        }
+        wallpaper.format
+    }
 
-    wallpapers.collect()
+    fn scan_dir_entries(&mut self, dir_entries: std::fs::ReadDir, prefix: &str, depth_left: usize) {
+        for dir_entry in dir_entries.filter_map(|dir_entry| dir_entry.ok()) {
+            let file_name = dir_entry
+                .file_name()
+                .into_string()
+                .unwrap_or_else(|_| panic!("Invalid Unicode file name: {:?}", dir_entry));
+            let path = format!("{prefix}{file_name}");
+
+            // Symlinked directories aren't followed as they could form a cycle
+            if dir_entry
+                .file_type()
+                .is_ok_and(|file_type| file_type.is_dir())
+            {
+                if depth_left == 0 || file_name.starts_with('.') {
+                    continue;
+                }
+                if let Some(reason) = self.filter.check(&path, true) {
+                    self.report.ignored.push((format!("{path}/"), reason));
+                    continue;
+                }
+                match dir_entry.path().read_dir() {
+                    Ok(sub_dir_entries) => {
+                        self.scan_dir_entries(sub_dir_entries, &format!("{path}/"), depth_left - 1)
+                    }
+                    Err(err) => {
+                        eprintln!("Warning: can't read {}: {err}", dir_entry.path().display())
+                    }
+                }
+            } else {
+                // Only files with the extension of an image are looked into, opening anything
+                // else could block, as on a FIFO, or read every other file on every scan
+                let Some(extension_format) = dir_entry
+                    .path()
+                    .extension()
+                    .and_then(ImageFormat::from_extension)
+                else {
+                    continue;
+                };
+                if let Some(reason) = self.filter.check(&path, false) {
+                    self.report.ignored.push((path, reason));
+                    continue;
+                }
+                // Follows symlinks, one to a directory isn't descended into but isn't read either
+                let metadata = match dir_entry.path().metadata() {
+                    Ok(metadata) if metadata.is_file() => metadata,
+                    Ok(_) => continue,
+                    Err(err) => {
+                        eprintln!("Warning: can't read {}: {err}", dir_entry.path().display());
+                        continue;
+                    }
+                };
+
+                let content_format = match self.known_format(&path, &metadata) {
+                    // Recorded as TGA only when its content wasn't recognised
+                    Some(known_format) => Some(known_format).filter(|f| *f != ImageFormat::Tga),
+                    None => match format::sniff(&dir_entry.path()) {
+                        Ok(content_format) => content_format,
+                        Err(err) => {
+                            eprintln!("Warning: can't read {}: {err}", dir_entry.path().display());
+                            continue;
+                        }
+                    },
+                };
+                match (extension_format, content_format) {
+                    (extension_format, Some(content_format))
+                        if extension_format != content_format =>
+                    {
+                        self.report.mismatched.push((
+                            path.clone(),
+                            extension_format,
+                            content_format,
+                        ));
+                        self.wallpapers.push((path, content_format));
+                    }
+                    (_, Some(content_format)) => self.wallpapers.push((path, content_format)),
+                    (ImageFormat::Tga, None) => {
+                        self.report.unverified.push(path.clone());
+                        self.wallpapers.push((path, ImageFormat::Tga));
+                    }
+                    (extension_format, None) => self
+                        .report
+                        .ignored
+                        .push((path, IgnoreReason::NotAnImage(extension_format))),
+                }
+            }
+        }
+    }
+}
+
+pub(crate) const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// One step of 64-bit FNV-1a, a simple hash that unlike `DefaultHasher` is stable across builds
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
 }
 
-fn is_img_file(extension: &std::ffi::OsStr) -> bool {
-    let ext_str = extension.to_string_lossy().to_string();
+/// Number of bytes hashed at both ends of a file, hashing whole images of a large library would
+/// read every byte of it on the first sync
+const HASH_EDGE_LEN: u64 = 64 * 1024;
+
+/// 64-bit FNV-1a of the file size and its first and last `HASH_EDGE_LEN` bytes, stable across
+/// builds so it can be persisted in the state.
+///
+/// The middle of larger files isn't hashed, and by the time a match is found the removed file is
+/// gone so it can't be compared in full. Two files of the same size that only differ in the
+/// middle are taken for the same wallpaper, so a new file can inherit the history of a removed
+/// one it merely shares its size and edges with. Compressed formats make that unlikely, an
+/// uncompressed image edited in place without changing its size is the case to expect
+fn hash_file(path: &std::path::Path, size: u64) -> Result<u64, std::io::Error> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = vec![];
+    (&mut file).take(HASH_EDGE_LEN).read_to_end(&mut buffer)?;
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, &size.to_le_bytes());
+    hash = fnv1a(hash, &buffer);
+
+    if size > HASH_EDGE_LEN {
+        buffer.clear();
+        file.seek(SeekFrom::Start(
+            size.saturating_sub(HASH_EDGE_LEN).max(HASH_EDGE_LEN),
+        ))?;
+        file.take(HASH_EDGE_LEN).read_to_end(&mut buffer)?;
+        hash = fnv1a(hash, &buffer);
+    }
+
+    Ok(hash)
+}
 
-    matches!(
-        ext_str.as_str(),
-        "jpg" | "jpeg" | "png" | "gif" | "pnm" | "tga" | "tiff" | "webp" | "bmp" | "farbfeld"
-    )
+/// Modification time of the file as a Unix timestamp in seconds, 0 if unknown
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|mtime| mtime.as_secs())
+        .unwrap_or(0)
 }
 
-fn get_random_num(to: f64) -> f64 {
-    let mut rng = rand_hc::Hc128Rng::from_entropy();
-    rng.gen_range(0.0..to)
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
@@ -66,6 +66,26 @@ impl WallSetter {
         Ok(())
     }
 
+    /// Name of the program actually used to set wallpapers
+    pub fn backend(&self) -> &'static str {
+        if self.is_running_under_wayland() {
+            match &self.program {
+                WallSetterProgram::SWWW => "swww",
+                WallSetterProgram::PLASMA => "plasma-apply-wallpaperimage",
+                #[cfg(feature = "hyprpaper")]
+                WallSetterProgram::HYPRPAPER => "hyprpaper",
+            }
+        } else {
+            "feh"
+        }
+    }
+
+    /// Monitors the wallpaper is set on. None of the programs is given a particular one: swww
+    /// and feh cover every output by default, hyprpaper is given an empty monitor name
+    pub fn monitor(&self) -> &'static str {
+        "all"
+    }
+
     pub fn is_running(&self) -> bool {
         let output = std::process::Command::new("pgrep")
             .arg("-c")
@@ -90,10 +110,7 @@ impl WallSetter {
 
         if !output.status.success() {
             eprintln!("{:?}", output.stderr);
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", output),
-            ));
+            return Err(std::io::Error::other(format!("{:?}", output)));
         }
 
         if Self::is_swww_daemon_running()? {
@@ -128,10 +145,7 @@ impl WallSetter {
 
             if !output.status.success() {
                 eprintln!("{:?}", output.stderr);
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("{:?}", output),
-                ));
+                return Err(std::io::Error::other(format!("{:?}", output)));
             }
         }
 
@@ -206,10 +220,7 @@ impl WallSetter {
 
             if !output.status.success() {
                 eprintln!("{:?}", output.stderr);
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("{:?}", output),
-                ));
+                return Err(std::io::Error::other(format!("{:?}", output)));
             }
         }
 
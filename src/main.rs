@@ -2,8 +2,18 @@
 use std::env;
 use wallrustler::wallpaper::WallSetter;
 use wallrustler::{
-    find_wallpaper_path, mean_centering_counts, pick_random_wallpaper, print_help, process_args,
-    retrieve_wallpapers, sync_wallpapers, Error, Option,
+    collection::{CollectionFilter, CollectionWeights, Shares},
+    export::{self, StateFormat},
+    find_host_name, find_sources, find_target,
+    history::{self, HistoryEntry, HistoryFilter},
+    mean_centering_counts,
+    merge::Peers,
+    pick_random_wallpaper, print_help, process_args, retrieve_wallpapers, save_wallpapers,
+    selection::{new_rng, Constraints, StrategyKind, Weighting},
+    source::{self, Source, Union},
+    state::{self, StateError},
+    sync_wallpapers, unix_now, Error, Option, Scan, ScanReport, Target, Wallpaper,
+    DEFAULT_REMOVED_RETENTION,
 };
 
 #[cfg(target_os = "linux")]
@@ -32,19 +42,237 @@ fn main() {
         print_help();
         std::process::exit(-1);
     }
-    if options.contains(&Option::PrintState) {
-        let wallpapers_dir_path = find_wallpaper_path(&options).unwrap();
-        let mut wallpapers = retrieve_wallpapers(wallpapers_dir_path);
-        wallpapers = sync_wallpapers(wallpapers_dir_path, wallpapers);
+    let sources = find_sources(&options).unwrap_or_else(|err| {
+        eprintln!("Failed to locate the state: {err}");
+        std::process::exit(-1);
+    });
+    for source in &sources {
+        let state_path = state::readable_path(&source.dir_path, &source.state_path);
+        if let Err(StateError::UnsupportedVersion(version)) = state::check_version(&state_path) {
+            eprintln!(
+                "{} holds state version {version}, which only a newer release can read",
+                state_path.display()
+            );
+            std::process::exit(-1);
+        }
+    }
 
-        let states: Vec<(String, usize)> = wallpapers
+    let removed_retention = options
+        .iter()
+        .find_map(|o| match o {
+            Option::KeepRemoved(days) => Some(days * 24 * 60 * 60),
+            _ => None,
+        })
+        .unwrap_or(DEFAULT_REMOVED_RETENTION);
+    let scan = Scan {
+        max_depth: options
+            .iter()
+            .find_map(|o| match o {
+                Option::Recursive(max_depth) => Some(*max_depth),
+                _ => None,
+            })
+            .unwrap_or(0),
+        include: options
+            .iter()
+            .filter_map(|o| match o {
+                Option::Include(pattern) => Some(pattern.clone()),
+                _ => None,
+            })
+            .collect(),
+        exclude: options
             .iter()
-            .map(|wallpaper| (wallpaper.file_name.to_owned(), wallpaper.count))
+            .filter_map(|o| match o {
+                Option::Exclude(pattern) => Some(pattern.clone()),
+                _ => None,
+            })
+            .collect(),
+    };
+
+    let host_name = find_host_name(&options);
+
+    if options.contains(&Option::History) {
+        let mut filter = HistoryFilter::default();
+        for option in &options {
+            match option {
+                Option::HistoryLast(last) => filter.last = filter.last.or(Some(*last)),
+                Option::HistorySince(since) => filter.since = filter.since.or(Some(*since)),
+                Option::HistoryUntil(until) => filter.until = filter.until.or(Some(*until)),
+                Option::HistoryFile(file_name) => {
+                    filter.file_name = filter.file_name.take().or(Some(file_name.clone()))
+                }
+                _ => {}
+            }
+        }
+
+        let state_dir_paths: Vec<&std::path::Path> = sources
+            .iter()
+            .map(|source| source.state_path.parent().unwrap())
             .collect();
-        let max_len = states.iter().map(|(name, _)| name.len()).max().unwrap();
-        for (name, count) in states {
-            println!("{:<max_len$}: {count}", name);
+        let entries = history::read(&state_dir_paths, &filter).unwrap_or_else(|err| {
+            eprintln!("Failed to read history: {err}");
+            std::process::exit(-1);
+        });
+        for entry in entries {
+            println!(
+                "{} {:>6}s {} {} {}",
+                history::format_timestamp(entry.timestamp),
+                entry.duration,
+                entry.backend,
+                entry.monitor,
+                entry.file_name
+            );
+        }
+        return;
+    }
+    if options.contains(&Option::PrintState) {
+        let (union, reports) = load_merged(&sources, &scan, removed_retention);
+
+        let max_len = union
+            .wallpapers
+            .iter()
+            .map(|w| w.file_name.len())
+            .max()
+            .unwrap_or(0);
+        for (index, wallpaper) in union.wallpapers.iter().enumerate() {
+            print_source_header(&sources, &union, index);
+            println!(
+                "{:<max_len$}: {:<6} {:>9} rating {}/5{}{}",
+                wallpaper.file_name,
+                wallpaper.count,
+                history::format_duration(wallpaper.seconds_shown),
+                wallpaper.rating,
+                if wallpaper.banned { " banned" } else { "" },
+                match wallpaper.removed {
+                    Some(removed) => format!(" removed {}", history::format_timestamp(removed)),
+                    None => String::new(),
+                }
+            );
+        }
+        for (source, report) in sources.iter().zip(reports) {
+            if !report.ignored.is_empty() {
+                println!("Ignored in {}:", source.dir_path.display());
+            }
+            for (path, reason) in report.ignored {
+                println!("\t{path}: {reason}");
+            }
+            if !report.mismatched.is_empty() {
+                println!("Mismatched extensions in {}:", source.dir_path.display());
+            }
+            for (path, extension_format, content_format) in report.mismatched {
+                println!(
+                    "\t{path}: {} content with a {} extension",
+                    content_format.name(),
+                    extension_format.name()
+                );
+            }
+            if !report.unverified.is_empty() {
+                println!(
+                    "Taken on their extension alone in {}:",
+                    source.dir_path.display()
+                );
+            }
+            for path in report.unverified {
+                println!("\t{path}: TGA has no signature to check the content against");
+            }
+        }
+        return;
+    }
+    if let Some(path) = options.iter().find_map(|o| match o {
+        Option::ExportState(path) => Some(path),
+        _ => None,
+    }) {
+        let (wallpapers_dir_path, wallpapers_state_path) =
+            single_source(&sources, "--export-state");
+        let mut wallpapers = retrieve_wallpapers(wallpapers_dir_path, wallpapers_state_path, &scan);
+        wallpapers = sync_wallpapers(wallpapers_dir_path, wallpapers, &scan, removed_retention);
+
+        let format = StateFormat::from_path(path).unwrap();
+        let exported = export::export(&wallpapers, format).unwrap_or_else(|err| {
+            eprintln!("Failed to export state: {err:?}");
+            std::process::exit(-1);
+        });
+        if let Err(err) = std::fs::write(path, exported) {
+            eprintln!("Failed to write {}: {err}", path.display());
+            std::process::exit(-1);
         }
+        println!(
+            "Exported {} wallpapers to {}",
+            wallpapers.len(),
+            path.display()
+        );
+        return;
+    }
+    if let Some(path) = options.iter().find_map(|o| match o {
+        Option::ImportState(path) => Some(path),
+        _ => None,
+    }) {
+        let (wallpapers_dir_path, wallpapers_state_path) =
+            single_source(&sources, "--import-state");
+        let _lock = lock_state(wallpapers_dir_path, wallpapers_state_path);
+        let mut wallpapers = retrieve_wallpapers(wallpapers_dir_path, wallpapers_state_path, &scan);
+
+        let format = StateFormat::from_path(path).unwrap();
+        let imported = std::fs::read_to_string(path)
+            .map_err(|err| format!("{err}"))
+            .and_then(|input| export::import(&input, format).map_err(|err| format!("{err:?}")))
+            .unwrap_or_else(|err| {
+                eprintln!("Failed to import {}: {err}", path.display());
+                std::process::exit(-1);
+            });
+        println!(
+            "Imported {} wallpapers from {}",
+            imported.len(),
+            path.display()
+        );
+
+        // Imported records replace the ones with the same name, the others are kept
+        for wallpaper in imported {
+            match wallpapers
+                .iter_mut()
+                .find(|w| w.file_name == wallpaper.file_name)
+            {
+                Some(existing) => *existing = wallpaper,
+                None => wallpapers.push(wallpaper),
+            }
+        }
+        wallpapers = sync_wallpapers(wallpapers_dir_path, wallpapers, &scan, removed_retention);
+        if let Err(err) = save_wallpapers(wallpapers_state_path, &wallpapers) {
+            eprintln!("Failed to save state: {err}");
+            std::process::exit(-1);
+        }
+        return;
+    }
+    if let Some((target, rating)) = options.iter().find_map(|o| match o {
+        Option::Rate(target, rating) => Some((target, *rating)),
+        _ => None,
+    }) {
+        edit_wallpaper(&sources, &scan, removed_retention, target, |wallpaper| {
+            wallpaper.rating = rating;
+            wallpaper.edited = unix_now();
+            println!("Rated {} {rating}/5", wallpaper.file_name);
+        });
+        return;
+    }
+    if let Some(target) = options.iter().find_map(|o| match o {
+        Option::Ban(target) => Some(target),
+        _ => None,
+    }) {
+        edit_wallpaper(&sources, &scan, removed_retention, target, |wallpaper| {
+            wallpaper.banned = true;
+            wallpaper.edited = unix_now();
+            println!("Banned {}", wallpaper.file_name);
+        });
+        return;
+    }
+    if let Some(target) = options.iter().find_map(|o| match o {
+        Option::Unban(target) => Some(target),
+        _ => None,
+    }) {
+        edit_wallpaper(&sources, &scan, removed_retention, target, |wallpaper| {
+            wallpaper.banned = false;
+            wallpaper.edited = unix_now();
+            println!("Unbanned {}", wallpaper.file_name);
+        });
         return;
     }
     #[cfg(target_os = "windows")]
@@ -59,17 +287,121 @@ fn main() {
         interval = m * 60;
     }
 
-    #[cfg(target_os = "linux")]
-    if let Ok(val) = env::var("XDG_CURRENT_DESKTOP") {
-        if val == "KDE"
-            && options
+    let strategy = options
+        .iter()
+        .find_map(|o| match o {
+            Option::Strategy(strategy) => Some(*strategy),
+            _ => None,
+        })
+        .unwrap_or(StrategyKind::Decay);
+    let weighting = Weighting {
+        decay: options
+            .iter()
+            .find_map(|o| match o {
+                Option::Decay(decay) => Some(*decay),
+                _ => None,
+            })
+            .unwrap_or_default(),
+        balance: options
+            .iter()
+            .find_map(|o| match o {
+                Option::Balance(balance) => Some(*balance),
+                _ => None,
+            })
+            .unwrap_or_default(),
+        interval,
+        recency_half_life: options.iter().find_map(|o| match o {
+            Option::Recency(hours) => Some(hours * 60 * 60),
+            _ => None,
+        }),
+    };
+    let mut strategy = strategy.build(weighting);
+    let constraints = Constraints {
+        no_repeat: options
+            .iter()
+            .find_map(|o| match o {
+                Option::NoRepeat(window) => Some(*window),
+                _ => None,
+            })
+            .unwrap_or(0),
+        fairness: options.iter().find_map(|o| match o {
+            Option::Fairness(rotations) => Some(*rotations),
+            _ => None,
+        }),
+        collections: CollectionFilter {
+            enabled: options.iter().find_map(|o| match o {
+                Option::Collections(collections) => Some(collections.clone()),
+                _ => None,
+            }),
+            disabled: options
                 .iter()
-                .find(|o| match o {
-                    Option::Program(_) => true,
-                    _ => false,
+                .find_map(|o| match o {
+                    Option::ExcludeCollections(collections) => Some(collections.clone()),
+                    _ => None,
                 })
-                .is_none()
-        {
+                .unwrap_or_default(),
+        },
+    };
+    let collection_weights = options
+        .iter()
+        .find_map(|o| match o {
+            Option::CollectionWeights(weights) => Some(weights.clone()),
+            _ => None,
+        })
+        .unwrap_or_default();
+    check_source_names(&collection_weights, &sources);
+    let seed = options.iter().find_map(|o| match o {
+        Option::Seed(seed) => Some(*seed),
+        _ => None,
+    });
+    let mut rng = new_rng(seed);
+
+    if options.contains(&Option::Simulate) {
+        let (mut union, _) = load_merged(&sources, &scan, removed_retention);
+        union.wallpapers = mean_centering_counts(std::mem::take(&mut union.wallpapers));
+        let shares = resolve_shares(&collection_weights, &sources, &union);
+        let wallpapers = &union.wallpapers;
+
+        let candidates = constraints.candidates(wallpapers);
+        strategy.prepare(wallpapers, &candidates, &shares);
+        let probabilities = match constraints.starved(wallpapers, &candidates) {
+            Some(starved) => candidates
+                .iter()
+                .map(|&i| if i == starved { 1.0 } else { 0.0 })
+                .collect(),
+            None => shares.probabilities(&candidates, |candidates| {
+                strategy.probabilities(wallpapers, candidates)
+            }),
+        };
+        let max_len = wallpapers
+            .iter()
+            .map(|w| w.file_name.len())
+            .max()
+            .unwrap_or(0);
+        for (index, wallpaper) in wallpapers.iter().enumerate() {
+            print_source_header(&sources, &union, index);
+            if wallpaper.removed.is_some() {
+                continue;
+            }
+            let probability = candidates
+                .iter()
+                .position(|&i| i == index)
+                .map(|position| probabilities[position])
+                .unwrap_or(0.0);
+            println!(
+                "{:<max_len$}: {:>6.2}% (count {}, shown {})",
+                wallpaper.file_name,
+                probability * 100.0,
+                wallpaper.count,
+                history::format_duration(wallpaper.seconds_shown)
+            );
+        }
+        return;
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Ok(val) = env::var("XDG_CURRENT_DESKTOP") {
+        if val == "KDE" && !options.iter().any(|o| matches!(o, Option::Program(_))) {
             println!("KDE detected, switching to plasma-apply-wallpaperimage as wallpaper setting program\nThis behavior can be changed by using --program option");
             wall_setter.set_program(WallSetterProgram::PLASMA);
         }
@@ -84,8 +416,6 @@ fn main() {
         wall_setter.set_program(*p);
     }
 
-    let wallpapers_dir_path = find_wallpaper_path(&options).unwrap();
-
     if !wall_setter.is_running() {
         wall_setter.init();
     } else {
@@ -94,19 +424,312 @@ fn main() {
         wall_setter.init();
     }
 
-    let mut wallpapers = retrieve_wallpapers(wallpapers_dir_path);
-    wallpapers = sync_wallpapers(wallpapers_dir_path, wallpapers);
-
-    let mut wallpapers_state_path = wallpapers_dir_path.clone();
-    wallpapers_state_path.push("state.bin");
+    let mut shown: std::option::Option<Shown> = None;
     loop {
-        wallpapers = sync_wallpapers(wallpapers_dir_path, wallpapers);
-        wallpapers = mean_centering_counts(wallpapers);
-        let wallpaper = pick_random_wallpaper(wallpapers_dir_path, &mut wallpapers);
-        wall_setter.set_wallpaper(&wallpaper).unwrap();
-        let state =
-            serde_binary::to_vec(&wallpapers, serde_binary::binary_stream::Endian::Little).unwrap();
-        std::fs::write(&wallpapers_state_path, state).unwrap();
+        // The wallpaper on screen is credited with the time it actually stayed there, its history
+        // entry is completed now that it is known
+        let shown_seconds = shown
+            .as_ref()
+            .map_or(0, |shown| shown.since.elapsed().as_secs());
+        if let Some(shown) = &mut shown {
+            shown.entry.duration = shown_seconds;
+            if let Some(offset) = shown.offset {
+                if let Err(err) = history::amend(&shown.history_path, offset, &shown.entry) {
+                    eprintln!("Failed to update {}: {err}", shown.history_path.display());
+                }
+            }
+        }
+
+        // Re-read the state every rotation so changes made by one-shot commands are kept
+        let locks = lock_sources(&sources);
+        let mut locals = vec![];
+        let mut peers = vec![];
+        let mut union = Union::default();
+        let lists = source::load_all(&sources, &scan, removed_retention);
+        for (source_index, (source, mut wallpapers)) in sources.iter().zip(lists).enumerate() {
+            if let Some(shown) = shown
+                .as_ref()
+                .filter(|shown| shown.source_index == source_index)
+            {
+                if let Some(wallpaper) = wallpapers
+                    .iter_mut()
+                    .find(|w| w.file_name == shown.file_name)
+                {
+                    wallpaper.seconds_shown += shown_seconds - shown.credited;
+                }
+            }
+            wallpapers = mean_centering_counts(wallpapers);
+            // Pick from the counts of all hosts but only save this host's share
+            let source_peers = read_peers(&source.state_path);
+            union.push(source_peers.merge(&wallpapers));
+            locals.push(wallpapers);
+            peers.push(source_peers);
+        }
+
+        let shares = resolve_shares(&collection_weights, &sources, &union);
+        let picked = pick_random_wallpaper(
+            &mut union.wallpapers,
+            strategy.as_mut(),
+            &constraints,
+            &shares,
+            &mut rng,
+        )
+        .map(|index| {
+            (
+                union.source_of(index),
+                union.wallpapers[index].file_name.clone(),
+            )
+        });
+
+        let no_pick = picked.is_none().then(|| no_pick_reason(&union.wallpapers));
+        let merged = union.split();
+        for (((source, local), source_peers), merged) in
+            sources.iter().zip(locals).zip(peers).zip(merged)
+        {
+            let wallpapers = source_peers.split(&local, merged);
+            if let Err(err) = save_wallpapers(&source.state_path, &wallpapers) {
+                eprintln!("Failed to save state: {err}");
+            }
+        }
+        drop(locks);
+        if let Some(shown) = &mut shown {
+            shown.credited = shown_seconds;
+        }
+
+        if let Some((source_index, file_name)) = picked {
+            let source = &sources[source_index];
+            wall_setter
+                .set_wallpaper(&source.dir_path.join(&file_name))
+                .unwrap();
+            let entry = HistoryEntry {
+                timestamp: unix_now(),
+                backend: wall_setter.backend().to_owned(),
+                monitor: wall_setter.monitor().to_owned(),
+                duration: 0,
+                file_name: file_name.clone(),
+            };
+            let history_path = history::host_path(source.state_path.parent().unwrap(), &host_name);
+            let offset = history::append(&history_path, &entry)
+                .map_err(|err| eprintln!("Failed to append to {}: {err}", history_path.display()))
+                .ok();
+            shown = Some(Shown {
+                source_index,
+                file_name,
+                since: std::time::Instant::now(),
+                credited: 0,
+                entry,
+                history_path,
+                offset,
+            });
+        } else if let Some(reason) = no_pick {
+            eprintln!("No wallpaper to pick, {reason}");
+        }
         std::thread::sleep(std::time::Duration::from_secs(interval));
     }
 }
+
+/// The wallpaper the daemon put on screen
+struct Shown {
+    source_index: usize,
+    file_name: String,
+    /// When it was set, `Instant` doesn't advance while suspended
+    since: std::time::Instant,
+    /// Seconds already added to `Wallpaper::seconds_shown`, it stays on screen when nothing else
+    /// could be picked
+    credited: u64,
+    entry: HistoryEntry,
+    history_path: std::path::PathBuf,
+    /// Where the entry starts in the history, `None` if it couldn't be written
+    offset: std::option::Option<u64>,
+}
+
+/// Applies a one-shot command to the targeted wallpaper and saves the state of the sources
+fn edit_wallpaper(
+    sources: &[Source],
+    scan: &Scan,
+    removed_retention: u64,
+    target: &Target,
+    edit: impl FnOnce(&mut Wallpaper),
+) {
+    let _locks = lock_sources(sources);
+    let mut locals = vec![];
+    let mut peers = vec![];
+    let mut union = Union::default();
+    for (source, wallpapers) in
+        sources
+            .iter()
+            .zip(source::load_all(sources, scan, removed_retention))
+    {
+        // The target and the edit see the other hosts, only this host's share is saved
+        let source_peers = read_peers(&source.state_path);
+        union.push(source_peers.merge(&wallpapers));
+        locals.push(wallpapers);
+        peers.push(source_peers);
+    }
+
+    let index = match target {
+        // The other hosts may have shown a wallpaper since, the current one is what this host
+        // shows. Merging keeps the order, so the index is the same in `union`
+        Target::Current => find_target(&locals.concat(), target),
+        Target::File(_) => find_target(&union.wallpapers, target),
+    };
+    let Some(index) = index else {
+        match target {
+            Target::Current => eprintln!("No wallpaper has been shown yet"),
+            Target::File(file_name) => eprintln!("{file_name} is not a known wallpaper"),
+        }
+        std::process::exit(-1);
+    };
+    edit(&mut union.wallpapers[index]);
+
+    // Every source is saved, loading may have moved a wallpaper from one to another
+    for (((source, local), source_peers), merged) in
+        sources.iter().zip(locals).zip(peers).zip(union.split())
+    {
+        let wallpapers = source_peers.split(&local, merged);
+        if let Err(err) = save_wallpapers(&source.state_path, &wallpapers) {
+            eprintln!("Failed to save state: {err}");
+            std::process::exit(-1);
+        }
+    }
+}
+
+/// Wallpapers of every source with the shares of the other hosts merged in, along with the scan
+/// report of every source
+fn load_merged(
+    sources: &[Source],
+    scan: &Scan,
+    removed_retention: u64,
+) -> (Union, Vec<ScanReport>) {
+    let (lists, reports) = source::load_all_with_reports(sources, scan, removed_retention);
+    let mut union = Union::default();
+    for (source, wallpapers) in sources.iter().zip(lists) {
+        union.push(read_peers(&source.state_path).merge(&wallpapers));
+    }
+    (union, reports)
+}
+
+/// Weights name sources by directory name, which has to tell them apart. A name that is also a
+/// subdirectory of another source weights both
+fn check_source_names(collection_weights: &CollectionWeights, sources: &[Source]) {
+    for (name, _) in &collection_weights.weights {
+        let source_name = name.split('/').next().unwrap_or(name);
+        let named: Vec<&Source> = sources
+            .iter()
+            .filter(|source| source.name() == source_name)
+            .collect();
+        if let [first, second, ..] = named.as_slice() {
+            eprintln!(
+                "{} and {} are both named {source_name}, rename one of them to weight it",
+                first.dir_path.display(),
+                second.dir_path.display()
+            );
+            std::process::exit(-1);
+        }
+        if named.is_empty() {
+            continue;
+        }
+        for source in sources {
+            if source.name() != source_name && source.dir_path.join(name).is_dir() {
+                eprintln!(
+                    "Warning: {name} names both the source {} and a collection of {}, its weight applies to both",
+                    named[0].dir_path.display(),
+                    source.dir_path.display()
+                );
+            }
+        }
+    }
+}
+
+/// Why none of the wallpapers could be picked
+fn no_pick_reason(wallpapers: &[Wallpaper]) -> &'static str {
+    let present: Vec<&Wallpaper> = wallpapers
+        .iter()
+        .filter(|wallpaper| wallpaper.removed.is_none())
+        .collect();
+    if present.is_empty() {
+        "the directories are empty"
+    } else if present.iter().all(|wallpaper| wallpaper.banned) {
+        "every wallpaper is banned"
+    } else {
+        "no wallpaper that isn't banned is in the enabled collections"
+    }
+}
+
+fn resolve_shares(
+    collection_weights: &CollectionWeights,
+    sources: &[Source],
+    union: &Union,
+) -> Shares {
+    let source_names: Vec<String> = sources.iter().map(Source::name).collect();
+    collection_weights.resolve(
+        union
+            .wallpapers
+            .iter()
+            .enumerate()
+            .map(|(index, wallpaper)| {
+                (
+                    source_names[union.source_of(index)].as_str(),
+                    wallpaper.file_name.as_str(),
+                )
+            }),
+    )
+}
+
+/// Names the source before its first wallpaper when listing several sources
+fn print_source_header(sources: &[Source], union: &Union, index: usize) {
+    let source_index = union.source_of(index);
+    if sources.len() > 1 && (index == 0 || union.source_of(index - 1) != source_index) {
+        println!("{}:", sources[source_index].dir_path.display());
+    }
+}
+
+/// The only source, for commands that work on a single directory
+fn single_source<'a>(
+    sources: &'a [Source],
+    command: &str,
+) -> (&'a std::path::Path, &'a std::path::Path) {
+    match sources {
+        [source] => (&source.dir_path, &source.state_path),
+        _ => {
+            eprintln!("{command} works on a single directory");
+            std::process::exit(-1);
+        }
+    }
+}
+
+fn read_peers(wallpapers_state_path: &std::path::Path) -> Peers {
+    let peers = Peers::read(wallpapers_state_path);
+    if !peers.host_names.is_empty() {
+        println!("Merging state of {}", peers.host_names.join(", "));
+    }
+    peers
+}
+
+/// Locks the state of a wallpaper directory, then moves a state left behind by an older release
+/// in place now that nothing else can be reading it
+fn lock_state(
+    wallpapers_dir_path: &std::path::Path,
+    wallpapers_state_path: &std::path::Path,
+) -> state::StateLock {
+    let lock = state::lock(wallpapers_state_path).unwrap_or_else(|err| {
+        eprintln!("Failed to lock the state: {err}");
+        std::process::exit(-1);
+    });
+    if let Err(err) = state::migrate(wallpapers_dir_path, wallpapers_state_path) {
+        eprintln!("Failed to move the state: {err}");
+        std::process::exit(-1);
+    }
+    lock
+}
+
+/// Locks the states of all sources, always in the same order so that two invocations listing
+/// the sources differently can't deadlock
+fn lock_sources(sources: &[Source]) -> Vec<state::StateLock> {
+    let mut sources: Vec<&Source> = sources.iter().collect();
+    sources.sort_by(|a, b| a.state_path.cmp(&b.state_path));
+    sources
+        .into_iter()
+        .map(|source| lock_state(&source.dir_path, &source.state_path))
+        .collect()
+}
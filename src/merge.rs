@@ -0,0 +1,123 @@
+//! Merging the states that several hosts keep for the same wallpaper directory, e.g. a directory
+//! synced between machines.
+//!
+//! Every host only ever writes its own state, which holds its own share of the counts. Merging
+//! sums the shares of all hosts, and as a host's share is replaced rather than added to when it
+//! changes, merging the same states again never counts anything twice. Last shown times are
+//! merged by keeping the latest one, ratings and bans by keeping the most recently edited one.
+//!
+//! Hosts only see each other's states in a directory they all sync. The default state directory
+//! under `$XDG_STATE_HOME` is private to the machine, so every host sharing a wallpaper directory
+//! has to run with `--state-in-dir`.
+
+use crate::Wallpaper;
+use std::collections::HashMap;
+
+/// What the other hosts add to a wallpaper
+#[derive(Debug, Default)]
+struct Share {
+    count: usize,
+    seconds_shown: u64,
+    last_shown: Option<u64>,
+    rating: u8,
+    banned: bool,
+    edited: u64,
+}
+
+impl Share {
+    fn add(&mut self, wallpaper: &Wallpaper) {
+        self.count += wallpaper.count;
+        self.seconds_shown += wallpaper.seconds_shown;
+        self.last_shown = self.last_shown.max(wallpaper.last_shown);
+        if wallpaper.edited > self.edited {
+            self.rating = wallpaper.rating;
+            self.banned = wallpaper.banned;
+            self.edited = wallpaper.edited;
+        }
+    }
+}
+
+/// Shares of the other hosts, by file name
+#[derive(Debug, Default)]
+pub struct Peers {
+    pub host_names: Vec<String>,
+    shares: HashMap<String, Share>,
+    /// File names by hash, for wallpapers another host still knows under an older name
+    file_names: HashMap<u64, String>,
+}
+
+impl Peers {
+    /// Reads the states the other hosts keep next to `host_state_path`
+    pub fn read(host_state_path: &std::path::Path) -> Peers {
+        let mut peers = Peers::default();
+        for (host_name, state_path) in crate::state::peer_paths(host_state_path) {
+            let Some(wallpapers) = crate::state::read(&state_path) else {
+                continue;
+            };
+            for wallpaper in &wallpapers {
+                if let Some(hash) = wallpaper.hash {
+                    peers
+                        .file_names
+                        .entry(hash)
+                        .or_insert_with(|| wallpaper.file_name.clone());
+                }
+                peers
+                    .shares
+                    .entry(wallpaper.file_name.clone())
+                    .or_default()
+                    .add(wallpaper);
+            }
+            peers.host_names.push(host_name);
+        }
+
+        peers
+    }
+
+    fn share(&self, wallpaper: &Wallpaper) -> Option<&Share> {
+        self.shares.get(&wallpaper.file_name).or_else(|| {
+            let file_name = self.file_names.get(&wallpaper.hash?)?;
+            self.shares.get(file_name)
+        })
+    }
+
+    /// Wallpapers with the shares of the other hosts added
+    pub fn merge(&self, wallpapers: &[Wallpaper]) -> Vec<Wallpaper> {
+        wallpapers
+            .iter()
+            .map(|wallpaper| {
+                let mut merged = wallpaper.clone();
+                if let Some(share) = self.share(wallpaper) {
+                    merged.count += share.count;
+                    merged.seconds_shown += share.seconds_shown;
+                    merged.last_shown = merged.last_shown.max(share.last_shown);
+                    if share.edited > merged.edited {
+                        merged.rating = share.rating;
+                        merged.banned = share.banned;
+                        merged.edited = share.edited;
+                    }
+                }
+                merged
+            })
+            .collect()
+    }
+
+    /// Takes the shares of the other hosts back out of `merged`, which came from `merge(local)`
+    /// and was changed since
+    pub fn split(&self, local: &[Wallpaper], merged: Vec<Wallpaper>) -> Vec<Wallpaper> {
+        local
+            .iter()
+            .zip(merged)
+            .map(|(local, mut merged)| {
+                if let Some(share) = self.share(local) {
+                    merged.count = merged.count.saturating_sub(share.count);
+                    merged.seconds_shown = merged.seconds_shown.saturating_sub(share.seconds_shown);
+                    // Unless this host showed the wallpaper since, the time is another host's
+                    if merged.last_shown == local.last_shown.max(share.last_shown) {
+                        merged.last_shown = local.last_shown;
+                    }
+                }
+                merged
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,318 @@
+//! A rotation keeps what picking depends on indexed, so that once it is built in O(n log n)
+//! every pick takes O(log n). The candidates and their weights sit in Fenwick trees ordered by
+//! collection and file name, the no-repeat window is a ring of the latest picks, and the
+//! fairness limit is measured against a single rotation count rather than a counter that every
+//! wallpaper would need bumped on every pick.
+
+use crate::collection::Shares;
+use crate::selection::{Constraints, SelectionStrategy, WeightTree};
+use crate::Wallpaper;
+use rand::RngCore;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::Range;
+
+pub struct Rotation {
+    /// Collection and index of the eligible wallpapers that belong to a collection, sorted by
+    /// collection, file name and index
+    order: Vec<(usize, usize)>,
+    /// Position in `order` of every wallpaper that has one
+    positions: Vec<Option<usize>>,
+    /// Positions of every collection in `order`
+    ranges: Vec<Range<usize>>,
+    collection_weights: Vec<f64>,
+    /// Weight of every collection that has candidates, 0 for the others
+    collections: WeightTree,
+    /// Number of candidates in every collection
+    collection_lens: Vec<usize>,
+    /// 1 for every position holding a candidate, 0 for the recently shown ones
+    candidates: WeightTree,
+    /// Weight the strategy gives every candidate, 0 for the recently shown ones
+    weights: WeightTree,
+    /// Most recently shown wallpapers that can't be picked again yet, oldest first
+    recent: VecDeque<usize>,
+    window: usize,
+    eligible: Vec<bool>,
+    /// Picks since the rotation was built
+    rotation: i64,
+    /// Rotation every eligible wallpaper was last shown at, negative if before the build
+    shown_at: Vec<i64>,
+    /// Candidates by the rotation they were last shown at, kept only with a fairness limit
+    waiting: BTreeSet<(i64, Reverse<usize>)>,
+    fairness: Option<usize>,
+    /// Most recently shown wallpaper
+    latest: Option<usize>,
+}
+
+impl Rotation {
+    pub fn new(
+        wallpapers: &[Wallpaper],
+        strategy: &mut dyn SelectionStrategy,
+        constraints: &Constraints,
+        shares: &Shares,
+    ) -> Rotation {
+        let eligible: Vec<bool> = wallpapers
+            .iter()
+            .map(|wallpaper| constraints.eligible(wallpaper))
+            .collect();
+        let eligible_indices: Vec<usize> = (0..wallpapers.len()).filter(|&i| eligible[i]).collect();
+        let recent = constraints.recent(wallpapers, &eligible_indices);
+        let mut is_recent = vec![false; wallpapers.len()];
+        recent.iter().for_each(|&i| is_recent[i] = true);
+        let candidates: Vec<usize> = eligible_indices
+            .iter()
+            .copied()
+            .filter(|&i| !is_recent[i])
+            .collect();
+        strategy.prepare(wallpapers, &candidates, shares);
+
+        let mut order: Vec<(usize, usize)> = eligible_indices
+            .iter()
+            .filter_map(|&i| Some((shares.collection_of(i)?, i)))
+            .collect();
+        order.sort_by(|&(a_collection, a), &(b_collection, b)| {
+            a_collection
+                .cmp(&b_collection)
+                .then_with(|| wallpapers[a].file_name.cmp(&wallpapers[b].file_name))
+                .then(a.cmp(&b))
+        });
+        let mut positions = vec![None; wallpapers.len()];
+        for (position, &(_, index)) in order.iter().enumerate() {
+            positions[index] = Some(position);
+        }
+
+        let collection_weights = shares.weights();
+        let ranges = (0..collection_weights.len())
+            .map(|collection| {
+                order.partition_point(|&(c, _)| c < collection)
+                    ..order.partition_point(|&(c, _)| c <= collection)
+            })
+            .collect();
+        let mut shown_at = vec![0; wallpapers.len()];
+        for &index in &eligible_indices {
+            shown_at[index] = -(wallpapers[index].rotations_since_shown as i64);
+        }
+
+        let mut rotation = Rotation {
+            positions,
+            ranges,
+            collections: WeightTree::new(vec![0.0; collection_weights.len()]),
+            collection_lens: vec![0; collection_weights.len()],
+            collection_weights,
+            candidates: WeightTree::new(vec![0.0; order.len()]),
+            weights: WeightTree::new(vec![0.0; order.len()]),
+            order,
+            window: constraints
+                .no_repeat
+                .min(eligible_indices.len().saturating_sub(1)),
+            recent: recent.into(),
+            eligible,
+            rotation: 0,
+            shown_at,
+            waiting: BTreeSet::new(),
+            fairness: constraints.fairness,
+            latest: crate::selection::latest(wallpapers),
+        };
+        for index in candidates {
+            rotation.unblock(wallpapers, strategy, index);
+        }
+        rotation
+    }
+
+    /// Index of the wallpaper to show next, recording that it was shown
+    pub fn pick(
+        &mut self,
+        wallpapers: &mut [Wallpaper],
+        strategy: &mut dyn SelectionStrategy,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        let index = match self.starved() {
+            Some(index) => index,
+            None => {
+                let collection = self.collections.sample(rng)?;
+                let range = self.ranges[collection].clone();
+                let mut pool = Pool {
+                    rotation: self,
+                    range,
+                };
+                strategy.pick(wallpapers, &mut pool, rng)?
+            }
+        };
+
+        self.rotation += 1;
+        if self.waiting.remove(&(self.shown_at[index], Reverse(index))) {
+            self.waiting.insert((self.rotation, Reverse(index)));
+        }
+        self.shown_at[index] = self.rotation;
+        let wallpaper = &mut wallpapers[index];
+        wallpaper.count += 1;
+        wallpaper.last_shown = Some(crate::unix_now());
+        self.latest = Some(index);
+        if let Some(position) = self.positions[index] {
+            self.weights
+                .set(position, strategy.weight(wallpapers, index));
+        }
+
+        if self.window > 0 {
+            self.block(index);
+            self.recent.push_back(index);
+            if self.recent.len() > self.window {
+                if let Some(index) = self.recent.pop_front() {
+                    self.unblock(wallpapers, strategy, index);
+                }
+            }
+        }
+
+        Some(index)
+    }
+
+    /// Brings `Wallpaper::rotations_since_shown` of the eligible wallpapers up to date, picking
+    /// only counts rotations. Takes O(n)
+    pub fn finish(self, wallpapers: &mut [Wallpaper]) {
+        for (index, wallpaper) in wallpapers.iter_mut().enumerate() {
+            if self.eligible[index] {
+                wallpaper.rotations_since_shown = (self.rotation - self.shown_at[index]) as usize;
+            }
+        }
+    }
+
+    /// The candidate that went unseen the longest, if it reached the fairness limit
+    fn starved(&self) -> Option<usize> {
+        let limit = self.fairness?;
+        let &(shown_at, Reverse(index)) = self.waiting.first()?;
+        (self.rotation - shown_at >= limit as i64).then_some(index)
+    }
+
+    /// Takes a wallpaper out of the candidates
+    fn block(&mut self, index: usize) {
+        self.waiting.remove(&(self.shown_at[index], Reverse(index)));
+        if let Some(position) = self.positions[index] {
+            self.candidates.set(position, 0.0);
+            self.weights.set(position, 0.0);
+            let collection = self.order[position].0;
+            self.collection_lens[collection] -= 1;
+            if self.collection_lens[collection] == 0 {
+                self.collections.set(collection, 0.0);
+            }
+        }
+    }
+
+    /// Makes a wallpaper a candidate again
+    fn unblock(
+        &mut self,
+        wallpapers: &[Wallpaper],
+        strategy: &dyn SelectionStrategy,
+        index: usize,
+    ) {
+        if self.fairness.is_some() {
+            self.waiting.insert((self.shown_at[index], Reverse(index)));
+        }
+        if let Some(position) = self.positions[index] {
+            self.candidates.set(position, 1.0);
+            self.weights
+                .set(position, strategy.weight(wallpapers, index));
+            let collection = self.order[position].0;
+            self.collection_lens[collection] += 1;
+            if self.collection_lens[collection] == 1 {
+                self.collections
+                    .set(collection, self.collection_weights[collection]);
+            }
+        }
+    }
+}
+
+/// Candidates of the collection a pick is made from, in file name order
+pub struct Pool<'a> {
+    rotation: &'a mut Rotation,
+    range: Range<usize>,
+}
+
+impl Pool<'_> {
+    fn index_at(&self, position: usize) -> usize {
+        self.rotation.order[position].1
+    }
+
+    pub fn len(&self) -> usize {
+        let candidates = &self.rotation.candidates;
+        (candidates.prefix(self.range.end) - candidates.prefix(self.range.start)) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Index of the `n`th candidate, `n` has to be below `len()`
+    pub fn nth(&self, n: usize) -> usize {
+        let candidates = &self.rotation.candidates;
+        let position = candidates.find(candidates.prefix(self.range.start) + n as f64);
+        self.index_at(position)
+    }
+
+    /// Every candidate, takes O(n)
+    pub fn candidates(&self) -> Vec<usize> {
+        self.range
+            .clone()
+            .filter(|&position| self.rotation.candidates.get(position) > 0.0)
+            .map(|position| self.index_at(position))
+            .collect()
+    }
+
+    pub fn total_weight(&self) -> f64 {
+        let weights = &self.rotation.weights;
+        weights.prefix(self.range.end) - weights.prefix(self.range.start)
+    }
+
+    /// Index of the candidate whose weight covers `target`, which has to be in
+    /// `0.0..total_weight()`
+    pub fn find(&self, target: f64) -> usize {
+        let weights = &self.rotation.weights;
+        let position = weights.find(weights.prefix(self.range.start) + target);
+        self.index_at(position)
+    }
+
+    /// Candidate drawn proportionally to its weight, `None` if every weight is 0
+    pub fn sample(&mut self, rng: &mut dyn RngCore) -> Option<usize> {
+        let position = self
+            .rotation
+            .weights
+            .sample_range(self.range.clone(), rng)?;
+        Some(self.index_at(position))
+    }
+
+    pub fn reweigh(&mut self, index: usize, weight: f64) {
+        if let Some(position) = self.rotation.positions[index] {
+            self.rotation.weights.set(position, weight);
+        }
+    }
+
+    /// The candidate following the most recently shown wallpaper in file name order, then index
+    /// order, wrapping around at the end
+    pub fn after_latest(&self, wallpapers: &[Wallpaper]) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let candidates = &self.rotation.candidates;
+        let next = match self.rotation.latest {
+            Some(latest) => match self.rotation.positions[latest]
+                .filter(|position| self.range.contains(position))
+            {
+                Some(position) => position + 1,
+                // Wallpapers of the same name in several sources follow each other by index
+                None => {
+                    let last = (&wallpapers[latest].file_name, latest);
+                    self.range.start
+                        + self.rotation.order[self.range.clone()]
+                            .partition_point(|&(_, i)| (&wallpapers[i].file_name, i) <= last)
+                }
+            },
+            None => self.range.start,
+        };
+        let mut before = candidates.prefix(next);
+        if before >= candidates.prefix(self.range.end) {
+            before = candidates.prefix(self.range.start);
+        }
+
+        Some(self.index_at(candidates.find(before)))
+    }
+}
@@ -0,0 +1,611 @@
+use crate::collection::{CollectionFilter, Shares};
+use crate::rotation::Pool;
+use crate::Wallpaper;
+use rand::prelude::*;
+use std::collections::HashSet;
+
+const COUNT_FACTOR: f64 = 1.001;
+
+pub trait SelectionStrategy {
+    /// Called with the candidates before picking from them or computing their odds, e.g. to find
+    /// the usage weights are relative to. A candidate is only ever weighed against the others of
+    /// its collection in `shares`
+    fn prepare(&mut self, wallpapers: &[Wallpaper], candidates: &[usize], shares: &Shares) {
+        let _ = (wallpapers, candidates, shares);
+    }
+
+    /// Weight of the wallpaper at `index` when the pool draws by weight
+    fn weight(&self, wallpapers: &[Wallpaper], index: usize) -> f64 {
+        let _ = (wallpapers, index);
+        1.0
+    }
+
+    /// Returns the index of the wallpaper to show next, chosen among the candidates of `pool`.
+    /// `None` if there is nothing to pick from
+    fn pick(
+        &mut self,
+        wallpapers: &mut [Wallpaper],
+        pool: &mut Pool,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize>;
+
+    /// Odds of every candidate, in the same order, of being picked next
+    fn probabilities(&self, wallpapers: &[Wallpaper], candidates: &[usize]) -> Vec<f64> {
+        let _ = wallpapers;
+        vec![1.0 / candidates.len() as f64; candidates.len()]
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StrategyKind {
+    Decay,
+    Uniform,
+    ShuffleBag,
+    Sequential,
+}
+
+impl StrategyKind {
+    pub fn from_name(name: &str) -> Option<StrategyKind> {
+        match name {
+            "decay" => Some(StrategyKind::Decay),
+            "uniform" => Some(StrategyKind::Uniform),
+            "shuffle" => Some(StrategyKind::ShuffleBag),
+            "sequential" => Some(StrategyKind::Sequential),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self, weighting: Weighting) -> Box<dyn SelectionStrategy> {
+        match self {
+            StrategyKind::Decay => Box::new(DecayWeighted::new(weighting)),
+            StrategyKind::Uniform => Box::new(Uniform),
+            StrategyKind::ShuffleBag => Box::new(ShuffleBag),
+            StrategyKind::Sequential => Box::new(Sequential),
+        }
+    }
+}
+
+/// Rules restricting which wallpapers a strategy may pick from
+#[derive(Debug, Default, Clone)]
+pub struct Constraints {
+    /// Number of most recently shown wallpapers that can't be picked again
+    pub no_repeat: usize,
+    /// Rotations after which a wallpaper that hasn't been shown is forced in
+    pub fairness: Option<usize>,
+    pub collections: CollectionFilter,
+}
+
+impl Constraints {
+    /// Whether the wallpaper takes part in the rotation at all, regardless of when it was shown
+    pub fn eligible(&self, wallpaper: &Wallpaper) -> bool {
+        !wallpaper.banned
+            && wallpaper.removed.is_none()
+            && self.collections.allows(&wallpaper.file_name)
+    }
+
+    /// Indices of the wallpapers that may be shown next
+    pub fn candidates(&self, wallpapers: &[Wallpaper]) -> Vec<usize> {
+        let mut candidates: Vec<usize> = (0..wallpapers.len())
+            .filter(|&i| self.eligible(&wallpapers[i]))
+            .collect();
+        let recent: HashSet<usize> = self.recent(wallpapers, &candidates).into_iter().collect();
+        candidates.retain(|i| !recent.contains(i));
+
+        candidates
+    }
+
+    /// The eligible wallpapers shown too recently to be picked again, oldest first
+    pub fn recent(&self, wallpapers: &[Wallpaper], eligible: &[usize]) -> Vec<usize> {
+        // The recently shown ring is ordered by `Wallpaper::last_shown`, when the library is
+        // smaller than the window it shrinks so that at least one wallpaper stays available
+        let window = self.no_repeat.min(eligible.len().saturating_sub(1));
+        if window == 0 {
+            return vec![];
+        }
+
+        let mut recent: Vec<usize> = eligible
+            .iter()
+            .copied()
+            .filter(|&i| wallpapers[i].last_shown.is_some())
+            .collect();
+        if recent.len() > window {
+            recent.select_nth_unstable_by_key(window, |&i| {
+                std::cmp::Reverse(wallpapers[i].last_shown)
+            });
+            recent.truncate(window);
+        }
+        recent.sort_by_key(|&i| wallpapers[i].last_shown);
+        recent
+    }
+
+    /// The candidate that went unseen the longest, if it reached the fairness limit
+    pub fn starved(&self, wallpapers: &[Wallpaper], candidates: &[usize]) -> Option<usize> {
+        let limit = self.fairness?;
+        candidates
+            .iter()
+            .copied()
+            .filter(|&i| wallpapers[i].rotations_since_shown >= limit)
+            .max_by_key(|&i| wallpapers[i].rotations_since_shown)
+    }
+}
+
+/// Curve turning how much a wallpaper was shown into a weight, usage is taken relative to the
+/// lowest one
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Decay {
+    /// `base^-count`
+    Exponential(f64),
+    /// Falls by the same amount for every pick, down to 1 for the most shown wallpaper
+    Linear,
+    /// `(1 + count)^-exponent`
+    InversePower(f64),
+    /// `e^(-count / temperature)`, higher temperatures flatten the distribution
+    Softmax(f64),
+}
+
+impl Default for Decay {
+    fn default() -> Self {
+        Decay::Exponential(COUNT_FACTOR)
+    }
+}
+
+impl Decay {
+    pub fn from_arg(arg: &str) -> Option<Decay> {
+        let (name, param) = match arg.split_once(':') {
+            Some((name, param)) => (
+                name,
+                Some(
+                    param
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|param| param.is_finite())?,
+                ),
+            ),
+            None => (arg, None),
+        };
+        match (name, param) {
+            ("exp", Some(base)) if base >= 1.0 => Some(Decay::Exponential(base)),
+            ("linear", None) => Some(Decay::Linear),
+            ("power", Some(exponent)) if exponent >= 0.0 => Some(Decay::InversePower(exponent)),
+            ("softmax", Some(temperature)) if temperature > 0.0 => {
+                Some(Decay::Softmax(temperature))
+            }
+            _ => None,
+        }
+    }
+
+    /// Natural logarithm of the weight, large counts would underflow the weight itself
+    fn log_weight(&self, count: f64, min: f64, max: f64) -> f64 {
+        let count = count - min;
+        match self {
+            Decay::Exponential(base) => -count * base.ln(),
+            Decay::Linear => (max - min - count.min(max - min) + 1.0).ln(),
+            Decay::InversePower(exponent) => -exponent * count.ln_1p(),
+            Decay::Softmax(temperature) => -count / temperature,
+        }
+    }
+}
+
+/// What the decay is applied to
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Balance {
+    /// Number of times a wallpaper was picked
+    #[default]
+    Count,
+    /// Time a wallpaper actually stayed on screen, in rotation intervals
+    Time,
+}
+
+impl Balance {
+    pub fn from_name(name: &str) -> Option<Balance> {
+        match name {
+            "count" => Some(Balance::Count),
+            "time" => Some(Balance::Time),
+            _ => None,
+        }
+    }
+}
+
+/// Settings shaping the weight of every wallpaper in `DecayWeighted`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Weighting {
+    pub decay: Decay,
+    pub balance: Balance,
+    /// Rotation interval in seconds, the unit of display time when balancing time
+    pub interval: u64,
+    /// Half-life in seconds of the penalty for recently shown wallpapers, `None` disables it
+    pub recency_half_life: Option<u64>,
+}
+
+impl Weighting {
+    /// Lowest and highest usage among the candidates, the decay is relative to them
+    fn range(&self, wallpapers: &[Wallpaper], candidates: &[usize]) -> (f64, f64) {
+        let usages = candidates.iter().map(|&i| self.usage(&wallpapers[i]));
+        let min = usages.clone().fold(f64::INFINITY, f64::min);
+        let max = usages.fold(f64::NEG_INFINITY, f64::max);
+        (min, max)
+    }
+
+    fn usage(&self, wallpaper: &Wallpaper) -> f64 {
+        match self.balance {
+            Balance::Count => wallpaper.count as f64,
+            Balance::Time => wallpaper.seconds_shown as f64 / self.interval.max(1) as f64,
+        }
+    }
+
+    fn log_weight(&self, wallpaper: &Wallpaper, min: f64, max: f64, now: u64) -> f64 {
+        let mut log_weight = self.decay.log_weight(self.usage(wallpaper), min, max);
+        log_weight += (wallpaper.rating as i32 - crate::DEFAULT_RATING as i32) as f64
+            * std::f64::consts::LN_2;
+
+        if let (Some(half_life), Some(last_shown)) = (self.recency_half_life, wallpaper.last_shown)
+        {
+            let age = now.saturating_sub(last_shown) as f64;
+            log_weight += (1.0 - 0.5f64.powf(age / half_life as f64)).ln();
+        }
+
+        log_weight
+    }
+}
+
+/// Weights in a Fenwick tree, drawing a position proportionally to its weight and changing a
+/// weight both take O(log n)
+#[derive(Debug, Default)]
+pub struct WeightTree {
+    weights: Vec<f64>,
+    /// `sums[i]` holds the weights of the positions `i - (i & -i)..i`, `sums[0]` is unused
+    sums: Vec<f64>,
+    /// Changes since the sums were last rebuilt, every one adds a little rounding error
+    changes: usize,
+}
+
+impl WeightTree {
+    pub fn new(weights: Vec<f64>) -> WeightTree {
+        let mut tree = WeightTree {
+            weights,
+            sums: vec![],
+            changes: 0,
+        };
+        tree.rebuild();
+        tree
+    }
+
+    fn rebuild(&mut self) {
+        self.sums = vec![0.0; self.weights.len() + 1];
+        for i in 1..self.sums.len() {
+            self.sums[i] += self.weights[i - 1];
+            let parent = i + (i & i.wrapping_neg());
+            if parent < self.sums.len() {
+                self.sums[parent] += self.sums[i];
+            }
+        }
+        self.changes = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn get(&self, position: usize) -> f64 {
+        self.weights[position]
+    }
+
+    pub fn set(&mut self, position: usize, weight: f64) {
+        let delta = weight - self.weights[position];
+        self.weights[position] = weight;
+
+        // Rebuilding once every `len` changes keeps the error bounded at no extra cost per change
+        self.changes += 1;
+        if self.changes > self.weights.len() {
+            return self.rebuild();
+        }
+        let mut i = position + 1;
+        while i < self.sums.len() {
+            self.sums[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Sum of the weights before `position`
+    pub fn prefix(&self, position: usize) -> f64 {
+        let mut sum = 0.0;
+        let mut i = position;
+        while i > 0 {
+            sum += self.sums[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+
+    pub fn total(&self) -> f64 {
+        self.prefix(self.weights.len())
+    }
+
+    /// Position of the weight covering `target`, which has to be in `0.0..total()`
+    pub fn find(&self, target: f64) -> usize {
+        let mut position = 0;
+        let mut remaining = target;
+        let mut step = self.weights.len().next_power_of_two();
+        while step > 0 {
+            let next = position + step;
+            if next < self.sums.len() && self.sums[next] <= remaining {
+                position = next;
+                remaining -= self.sums[next];
+            }
+            step /= 2;
+        }
+        position.min(self.weights.len() - 1)
+    }
+
+    /// Position drawn among `range` proportionally to the weights, `None` if they are all 0
+    pub fn sample_range(
+        &mut self,
+        range: std::ops::Range<usize>,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        for _ in 0..2 {
+            let low = self.prefix(range.start);
+            let total = self.prefix(range.end) - low;
+            // A NaN total can't be sampled from either
+            if total.is_nan() || total <= 0.0 {
+                return None;
+            }
+
+            let position = self.find(low + rng.gen_range(0.0..total));
+            if range.contains(&position) && self.weights[position] > 0.0 {
+                return Some(position);
+            }
+            // Only rounding errors land on a position without weight, draw again on exact sums
+            self.rebuild();
+        }
+        None
+    }
+
+    pub fn sample(&mut self, rng: &mut dyn RngCore) -> Option<usize> {
+        self.sample_range(0..self.weights.len(), rng)
+    }
+}
+
+/// Favours wallpapers that were shown less often, the weight falls with every pick along the
+/// configured `Decay` curve. With recency enabled wallpapers shown recently are additionally held back.
+/// Usage and recency are taken when the candidates are prepared, so a rotation built once and
+/// picked from many times keeps the weights of the moment it was built
+pub struct DecayWeighted {
+    weighting: Weighting,
+    /// Lowest and highest usage among the prepared candidates
+    range: (f64, f64),
+    now: u64,
+    /// Collection of every wallpaper, as an index into `offsets`
+    collection_of: Vec<Option<usize>>,
+    /// Largest log weight among the prepared candidates of every collection, scaling their
+    /// weights to at most 1. Picks are drawn within a collection, an offset shared by all of
+    /// them would underflow the weights of a collection used far more than the others
+    offsets: Vec<f64>,
+}
+
+impl DecayWeighted {
+    pub fn new(weighting: Weighting) -> DecayWeighted {
+        DecayWeighted {
+            weighting,
+            range: (0.0, 0.0),
+            now: 0,
+            collection_of: vec![],
+            offsets: vec![],
+        }
+    }
+
+    fn log_weight(&self, wallpaper: &Wallpaper) -> f64 {
+        let (min, max) = self.range;
+        self.weighting.log_weight(wallpaper, min, max, self.now)
+    }
+}
+
+impl SelectionStrategy for DecayWeighted {
+    fn prepare(&mut self, wallpapers: &[Wallpaper], candidates: &[usize], shares: &Shares) {
+        self.now = crate::unix_now();
+        self.range = self.weighting.range(wallpapers, candidates);
+        self.collection_of = (0..wallpapers.len())
+            .map(|i| shares.collection_of(i))
+            .collect();
+
+        let mut offsets = vec![f64::NEG_INFINITY; shares.weights().len()];
+        for &i in candidates {
+            if let Some(collection) = self.collection_of[i] {
+                offsets[collection] = offsets[collection].max(self.log_weight(&wallpapers[i]));
+            }
+        }
+        // A collection whose candidates were all shown recently gets them back one by one
+        for (i, wallpaper) in wallpapers.iter().enumerate() {
+            if let Some(collection) = self.collection_of[i] {
+                if offsets[collection] == f64::NEG_INFINITY
+                    && wallpaper.removed.is_none()
+                    && !wallpaper.banned
+                {
+                    offsets[collection] = self.log_weight(wallpaper);
+                }
+            }
+        }
+        self.offsets = offsets
+            .into_iter()
+            .map(|offset| {
+                if offset > f64::NEG_INFINITY {
+                    offset
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+    }
+
+    fn weight(&self, wallpapers: &[Wallpaper], index: usize) -> f64 {
+        let offset = self.collection_of[index].map_or(0.0, |collection| self.offsets[collection]);
+        (self.log_weight(&wallpapers[index]) - offset).exp()
+    }
+
+    fn pick(
+        &mut self,
+        wallpapers: &mut [Wallpaper],
+        pool: &mut Pool,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        match pool.sample(rng) {
+            Some(index) => Some(index),
+            None => Uniform.pick(wallpapers, pool, rng),
+        }
+    }
+
+    fn probabilities(&self, wallpapers: &[Wallpaper], candidates: &[usize]) -> Vec<f64> {
+        let weights: Vec<f64> = candidates
+            .iter()
+            .map(|&i| self.weight(wallpapers, i))
+            .collect();
+        let total_count_w: f64 = weights.iter().sum();
+        if total_count_w <= 0.0 {
+            return Uniform.probabilities(wallpapers, candidates);
+        }
+
+        weights
+            .iter()
+            .map(|weight| weight / total_count_w)
+            .collect()
+    }
+}
+
+/// Every wallpaper has the same odds regardless of its count
+pub struct Uniform;
+
+impl SelectionStrategy for Uniform {
+    fn pick(
+        &mut self,
+        _wallpapers: &mut [Wallpaper],
+        pool: &mut Pool,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        let position = (0..pool.len()).choose(rng)?;
+        Some(pool.nth(position))
+    }
+}
+
+/// Shows every wallpaper once, in random order, before any of them is repeated.
+/// The remaining bag is kept in `Wallpaper::in_bag` so it survives restarts, wallpapers added
+/// mid-cycle join the current bag and removed ones simply drop out of it
+pub struct ShuffleBag;
+
+impl SelectionStrategy for ShuffleBag {
+    fn weight(&self, wallpapers: &[Wallpaper], index: usize) -> f64 {
+        if wallpapers[index].in_bag {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn pick(
+        &mut self,
+        wallpapers: &mut [Wallpaper],
+        pool: &mut Pool,
+        rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        if pool.is_empty() {
+            return None;
+        }
+        if pool.total_weight() <= 0.0 {
+            for index in pool.candidates() {
+                wallpapers[index].in_bag = true;
+                pool.reweigh(index, 1.0);
+            }
+        }
+
+        // Every weight is 0 or 1, so the bag is the whole numbers below the total
+        let position = (0..pool.total_weight().round() as usize).choose(rng)?;
+        let index = pool.find(position as f64);
+        wallpapers[index].in_bag = false;
+
+        Some(index)
+    }
+
+    fn probabilities(&self, wallpapers: &[Wallpaper], candidates: &[usize]) -> Vec<f64> {
+        let bag_len = candidates.iter().filter(|&&i| wallpapers[i].in_bag).count();
+        candidates
+            .iter()
+            .map(|&i| match (bag_len, wallpapers[i].in_bag) {
+                (0, _) => 1.0 / candidates.len() as f64,
+                (_, true) => 1.0 / bag_len as f64,
+                (_, false) => 0.0,
+            })
+            .collect()
+    }
+}
+
+/// Walks through wallpapers in file name order, wrapping around at the end. Wallpapers of the
+/// same name in several sources follow each other in source order. It continues after
+/// the most recently shown wallpaper, which `Wallpaper::last_shown` keeps in the state so the
+/// walk survives restarts
+pub struct Sequential;
+
+impl Sequential {
+    fn next(&self, wallpapers: &[Wallpaper], candidates: &[usize]) -> Option<usize> {
+        let mut order = candidates.to_vec();
+        order.sort_by(|&a, &b| {
+            wallpapers[a]
+                .file_name
+                .cmp(&wallpapers[b].file_name)
+                .then(a.cmp(&b))
+        });
+
+        let next = match latest(wallpapers) {
+            Some(last) => order
+                .iter()
+                .find(|&&i| (&wallpapers[i].file_name, i) > (&wallpapers[last].file_name, last))
+                .or(order.first()),
+            None => order.first(),
+        };
+
+        next.copied()
+    }
+}
+
+impl SelectionStrategy for Sequential {
+    fn pick(
+        &mut self,
+        wallpapers: &mut [Wallpaper],
+        pool: &mut Pool,
+        _rng: &mut dyn RngCore,
+    ) -> Option<usize> {
+        pool.after_latest(wallpapers)
+    }
+
+    fn probabilities(&self, wallpapers: &[Wallpaper], candidates: &[usize]) -> Vec<f64> {
+        let next = self.next(wallpapers, candidates);
+        candidates
+            .iter()
+            .map(|&i| if Some(i) == next { 1.0 } else { 0.0 })
+            .collect()
+    }
+}
+
+/// Creates the session RNG, a fixed seed makes the whole sequence of picks reproducible
+pub fn new_rng(seed: Option<u64>) -> rand_hc::Hc128Rng {
+    match seed {
+        Some(seed) => rand_hc::Hc128Rng::seed_from_u64(seed),
+        None => rand_hc::Hc128Rng::from_entropy(),
+    }
+}
+
+/// Index of the most recently shown wallpaper. `Wallpaper::last_shown` only has a resolution of
+/// a second, of the wallpapers shown within the same one the fewest rotations ago is taken
+pub fn latest(wallpapers: &[Wallpaper]) -> Option<usize> {
+    (0..wallpapers.len())
+        .filter(|&i| wallpapers[i].last_shown.is_some())
+        .max_by_key(|&i| {
+            (
+                wallpapers[i].last_shown,
+                std::cmp::Reverse(wallpapers[i].rotations_since_shown),
+                i,
+            )
+        })
+}
@@ -0,0 +1,152 @@
+//! Wallpaper directories rotated together. Every directory keeps its own state, so adding or
+//! removing one leaves the others as they were.
+
+use crate::{Scan, ScanReport, Wallpaper};
+use std::collections::{HashMap, HashSet};
+
+/// A wallpaper directory and the state kept for it
+#[derive(Debug)]
+pub struct Source {
+    pub dir_path: std::path::PathBuf,
+    pub state_path: std::path::PathBuf,
+}
+
+impl Source {
+    /// Name of the directory, under which the source can be weighted like a collection
+    pub fn name(&self) -> String {
+        self.dir_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// The recorded wallpapers brought in line with the directory, with what the scan of the
+    /// directory left out or found odd
+    pub fn load(&self, scan: &Scan, removed_retention: u64) -> (Vec<Wallpaper>, ScanReport) {
+        let wallpapers = crate::retrieve_wallpapers(&self.dir_path, &self.state_path, scan);
+        crate::sync_wallpapers_with_report(&self.dir_path, wallpapers, scan, removed_retention)
+    }
+}
+
+/// The recorded wallpapers of every source brought in line with its directory, with the history
+/// of the files moved from one source to another following them
+pub fn load_all(sources: &[Source], scan: &Scan, removed_retention: u64) -> Vec<Vec<Wallpaper>> {
+    load_all_with_reports(sources, scan, removed_retention).0
+}
+
+/// `load_all`, along with the scan report of every source
+pub fn load_all_with_reports(
+    sources: &[Source],
+    scan: &Scan,
+    removed_retention: u64,
+) -> (Vec<Vec<Wallpaper>>, Vec<ScanReport>) {
+    let (mut lists, reports): (Vec<Vec<Wallpaper>>, Vec<ScanReport>) = sources
+        .iter()
+        .map(|source| source.load(scan, removed_retention))
+        .unzip();
+    match_moves(sources, &mut lists);
+    (lists, reports)
+}
+
+/// Syncing a source only sees renames within it, a file moved to another source leaves a removed
+/// record behind and shows up there without history. The removed record with the same content is
+/// moved over, the most recently removed first
+fn match_moves(sources: &[Source], lists: &mut [Vec<Wallpaper>]) {
+    let mut removed_by_hash: HashMap<u64, Vec<(usize, usize)>> = HashMap::new();
+    for (source_index, wallpapers) in lists.iter().enumerate() {
+        for (index, wallpaper) in wallpapers.iter().enumerate() {
+            if let (Some(hash), Some(_)) = (wallpaper.hash, wallpaper.removed) {
+                removed_by_hash
+                    .entry(hash)
+                    .or_default()
+                    .push((source_index, index));
+            }
+        }
+    }
+    removed_by_hash
+        .values_mut()
+        .for_each(|removed| removed.sort_by_key(|&(s, i)| lists[s][i].removed));
+
+    // From source and index to source and index
+    let mut moves = vec![];
+    for (source_index, wallpapers) in lists.iter().enumerate() {
+        for (index, wallpaper) in wallpapers.iter().enumerate() {
+            if wallpaper.removed.is_some() || !wallpaper.is_fresh() {
+                continue;
+            }
+            let Some(removed) = wallpaper
+                .hash
+                .and_then(|hash| removed_by_hash.get_mut(&hash))
+            else {
+                continue;
+            };
+            if let Some(position) = removed.iter().rposition(|&(s, _)| s != source_index) {
+                moves.push((removed.remove(position), (source_index, index)));
+            }
+        }
+    }
+
+    for &((from_source, from), (to_source, to)) in &moves {
+        let mut wallpaper = lists[from_source][from].clone();
+        let file = &lists[to_source][to];
+        println!(
+            "Moving {} to {}",
+            sources[from_source]
+                .dir_path
+                .join(&wallpaper.file_name)
+                .display(),
+            sources[to_source].dir_path.join(&file.file_name).display()
+        );
+        wallpaper.file_name = file.file_name.clone();
+        wallpaper.size = file.size;
+        wallpaper.mtime = file.mtime;
+        wallpaper.format = file.format;
+        wallpaper.removed = None;
+        lists[to_source][to] = wallpaper;
+    }
+
+    let moved: HashSet<(usize, usize)> = moves.iter().map(|&(from, _)| from).collect();
+    for (source_index, wallpapers) in lists.iter_mut().enumerate() {
+        let mut index = 0;
+        wallpapers.retain(|_| {
+            index += 1;
+            !moved.contains(&(source_index, index - 1))
+        });
+    }
+}
+
+/// Wallpapers of several sources in a single list, one source after the other
+#[derive(Debug, Default)]
+pub struct Union {
+    pub wallpapers: Vec<Wallpaper>,
+    lens: Vec<usize>,
+}
+
+impl Union {
+    /// Appends the wallpapers of the next source
+    pub fn push(&mut self, wallpapers: Vec<Wallpaper>) {
+        self.lens.push(wallpapers.len());
+        self.wallpapers.extend(wallpapers);
+    }
+
+    /// Index of the source the wallpaper at `index` belongs to
+    pub fn source_of(&self, index: usize) -> usize {
+        let mut end = 0;
+        self.lens
+            .iter()
+            .position(|len| {
+                end += len;
+                index < end
+            })
+            .unwrap_or(self.lens.len())
+    }
+
+    /// The wallpapers of every source, in the order they were pushed
+    pub fn split(self) -> Vec<Vec<Wallpaper>> {
+        let mut wallpapers = self.wallpapers.into_iter();
+        self.lens
+            .iter()
+            .map(|&len| wallpapers.by_ref().take(len).collect())
+            .collect()
+    }
+}
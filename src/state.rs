@@ -0,0 +1,590 @@
+//! On-disk format and location of `state.bin`.
+//!
+//! The state lives in `$XDG_STATE_HOME/wallrustler/`, in a directory named after the canonical
+//! path of the wallpaper directory, unless it is explicitly kept inside the wallpaper directory.
+//!
+//! A state file starts with `MAGIC` followed by the format version as a little-endian `u32`, the
+//! rest is a `serde_binary` dump of that version's layout. Files written before the header was
+//! introduced are version 0.
+//!
+//! Writes go through a temporary file that is synced and renamed over the state, the previous
+//! `BACKUP_COUNT` states are kept next to it as backups, the newest of them that can be read is
+//! used when the state can't.
+//!
+//! Every host keeps its own `state.<host>.bin` next to the others, so that a state directory
+//! synced between machines never has two writers for the same file, see `merge`.
+//!
+//! Read-modify-write cycles hold an advisory lock on a separate lock file, the state file itself
+//! is replaced on every write and can't carry the lock.
+//!
+//! To add a field to `Wallpaper`: freeze its current layout as `WallpaperV<CURRENT_VERSION>`,
+//! bump `CURRENT_VERSION`, and add a migration from the frozen layout to `decode`.
+
+use crate::Wallpaper;
+use serde::{Deserialize, Serialize};
+
+pub const STATE_FILE_NAME: &str = "state.bin";
+const MAGIC: &[u8; 4] = b"WRST";
+/// Previous states kept next to the state
+const BACKUP_COUNT: usize = 3;
+pub const CURRENT_VERSION: u32 = 5;
+const ENDIAN: serde_binary::binary_stream::Endian = serde_binary::binary_stream::Endian::Little;
+
+#[derive(Debug)]
+pub enum StateError {
+    /// Written by a newer release that this one can't read
+    UnsupportedVersion(u32),
+    Decode(serde_binary::Error),
+}
+
+/// Layout of version 0, a bare `Vec` without a header. Fields were added to it before the header
+/// was introduced, those missing from older files take their default value
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct WallpaperV0 {
+    file_name: String,
+    count: usize,
+    in_bag: bool,
+    last_shown: Option<u64>,
+    rating: u8,
+    banned: bool,
+    rotations_since_shown: usize,
+    hash: Option<u64>,
+    size: u64,
+    mtime: u64,
+}
+
+impl Default for WallpaperV0 {
+    fn default() -> Self {
+        let wallpaper = Wallpaper::new(String::new());
+        WallpaperV0 {
+            file_name: wallpaper.file_name,
+            count: wallpaper.count,
+            in_bag: wallpaper.in_bag,
+            last_shown: wallpaper.last_shown,
+            rating: wallpaper.rating,
+            banned: wallpaper.banned,
+            rotations_since_shown: wallpaper.rotations_since_shown,
+            hash: wallpaper.hash,
+            size: wallpaper.size,
+            mtime: wallpaper.mtime,
+        }
+    }
+}
+
+impl From<WallpaperV0> for Wallpaper {
+    fn from(wallpaper: WallpaperV0) -> Self {
+        let mut migrated = Wallpaper::new(wallpaper.file_name);
+        migrated.count = wallpaper.count;
+        migrated.in_bag = wallpaper.in_bag;
+        migrated.last_shown = wallpaper.last_shown;
+        migrated.rating = wallpaper.rating;
+        migrated.banned = wallpaper.banned;
+        migrated.rotations_since_shown = wallpaper.rotations_since_shown;
+        migrated.hash = wallpaper.hash;
+        migrated.size = wallpaper.size;
+        migrated.mtime = wallpaper.mtime;
+        migrated
+    }
+}
+
+/// Layout of version 1, before display time was tracked
+#[derive(Serialize, Deserialize)]
+struct WallpaperV1 {
+    file_name: String,
+    count: usize,
+    in_bag: bool,
+    last_shown: Option<u64>,
+    rating: u8,
+    banned: bool,
+    rotations_since_shown: usize,
+    hash: Option<u64>,
+    size: u64,
+    mtime: u64,
+}
+
+impl From<WallpaperV1> for Wallpaper {
+    fn from(wallpaper: WallpaperV1) -> Self {
+        let mut migrated = Wallpaper::new(wallpaper.file_name);
+        migrated.count = wallpaper.count;
+        migrated.in_bag = wallpaper.in_bag;
+        migrated.last_shown = wallpaper.last_shown;
+        migrated.rating = wallpaper.rating;
+        migrated.banned = wallpaper.banned;
+        migrated.rotations_since_shown = wallpaper.rotations_since_shown;
+        migrated.hash = wallpaper.hash;
+        migrated.size = wallpaper.size;
+        migrated.mtime = wallpaper.mtime;
+        migrated
+    }
+}
+
+/// Layout of version 2, before edits were timestamped for merging
+#[derive(Serialize, Deserialize)]
+struct WallpaperV2 {
+    file_name: String,
+    count: usize,
+    in_bag: bool,
+    last_shown: Option<u64>,
+    rating: u8,
+    banned: bool,
+    rotations_since_shown: usize,
+    hash: Option<u64>,
+    size: u64,
+    mtime: u64,
+    seconds_shown: u64,
+}
+
+impl From<WallpaperV2> for Wallpaper {
+    fn from(wallpaper: WallpaperV2) -> Self {
+        let mut migrated = Wallpaper::new(wallpaper.file_name);
+        migrated.count = wallpaper.count;
+        migrated.in_bag = wallpaper.in_bag;
+        migrated.last_shown = wallpaper.last_shown;
+        migrated.rating = wallpaper.rating;
+        migrated.banned = wallpaper.banned;
+        migrated.rotations_since_shown = wallpaper.rotations_since_shown;
+        migrated.hash = wallpaper.hash;
+        migrated.size = wallpaper.size;
+        migrated.mtime = wallpaper.mtime;
+        migrated.seconds_shown = wallpaper.seconds_shown;
+        migrated
+    }
+}
+
+/// Layout of version 3, before removed wallpapers were kept
+#[derive(Serialize, Deserialize)]
+struct WallpaperV3 {
+    file_name: String,
+    count: usize,
+    in_bag: bool,
+    last_shown: Option<u64>,
+    rating: u8,
+    banned: bool,
+    rotations_since_shown: usize,
+    hash: Option<u64>,
+    size: u64,
+    mtime: u64,
+    seconds_shown: u64,
+    edited: u64,
+}
+
+impl From<WallpaperV3> for Wallpaper {
+    fn from(wallpaper: WallpaperV3) -> Self {
+        let mut migrated = Wallpaper::new(wallpaper.file_name);
+        migrated.count = wallpaper.count;
+        migrated.in_bag = wallpaper.in_bag;
+        migrated.last_shown = wallpaper.last_shown;
+        migrated.rating = wallpaper.rating;
+        migrated.banned = wallpaper.banned;
+        migrated.rotations_since_shown = wallpaper.rotations_since_shown;
+        migrated.hash = wallpaper.hash;
+        migrated.size = wallpaper.size;
+        migrated.mtime = wallpaper.mtime;
+        migrated.seconds_shown = wallpaper.seconds_shown;
+        migrated.edited = wallpaper.edited;
+        migrated
+    }
+}
+
+/// Layout of version 4, before the format of the files was recorded
+#[derive(Serialize, Deserialize)]
+struct WallpaperV4 {
+    file_name: String,
+    count: usize,
+    in_bag: bool,
+    last_shown: Option<u64>,
+    rating: u8,
+    banned: bool,
+    rotations_since_shown: usize,
+    hash: Option<u64>,
+    size: u64,
+    mtime: u64,
+    seconds_shown: u64,
+    edited: u64,
+    removed: Option<u64>,
+}
+
+impl From<WallpaperV4> for Wallpaper {
+    fn from(wallpaper: WallpaperV4) -> Self {
+        let mut migrated = Wallpaper::new(wallpaper.file_name);
+        migrated.count = wallpaper.count;
+        migrated.in_bag = wallpaper.in_bag;
+        migrated.last_shown = wallpaper.last_shown;
+        migrated.rating = wallpaper.rating;
+        migrated.banned = wallpaper.banned;
+        migrated.rotations_since_shown = wallpaper.rotations_since_shown;
+        migrated.hash = wallpaper.hash;
+        migrated.size = wallpaper.size;
+        migrated.mtime = wallpaper.mtime;
+        migrated.seconds_shown = wallpaper.seconds_shown;
+        migrated.edited = wallpaper.edited;
+        migrated.removed = wallpaper.removed;
+        migrated
+    }
+}
+
+pub fn encode(wallpapers: &[Wallpaper]) -> Vec<u8> {
+    let mut state = MAGIC.to_vec();
+    state.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    state.extend(serde_binary::to_vec(&wallpapers, ENDIAN).unwrap());
+    state
+}
+
+pub fn decode(state: &[u8]) -> Result<Vec<Wallpaper>, StateError> {
+    let (version, payload) = match state.strip_prefix(MAGIC) {
+        Some(rest) if rest.len() >= 4 => {
+            let (version, payload) = rest.split_at(4);
+            (u32::from_le_bytes(version.try_into().unwrap()), payload)
+        }
+        _ => (0, state),
+    };
+
+    match version {
+        0 => serde_binary::from_slice::<Vec<WallpaperV0>>(payload, ENDIAN)
+            .map(|wallpapers| wallpapers.into_iter().map(Wallpaper::from).collect())
+            .map_err(StateError::Decode),
+        1 => serde_binary::from_slice::<Vec<WallpaperV1>>(payload, ENDIAN)
+            .map(|wallpapers| wallpapers.into_iter().map(Wallpaper::from).collect())
+            .map_err(StateError::Decode),
+        2 => serde_binary::from_slice::<Vec<WallpaperV2>>(payload, ENDIAN)
+            .map(|wallpapers| wallpapers.into_iter().map(Wallpaper::from).collect())
+            .map_err(StateError::Decode),
+        3 => serde_binary::from_slice::<Vec<WallpaperV3>>(payload, ENDIAN)
+            .map(|wallpapers| wallpapers.into_iter().map(Wallpaper::from).collect())
+            .map_err(StateError::Decode),
+        4 => serde_binary::from_slice::<Vec<WallpaperV4>>(payload, ENDIAN)
+            .map(|wallpapers| wallpapers.into_iter().map(Wallpaper::from).collect())
+            .map_err(StateError::Decode),
+        CURRENT_VERSION => serde_binary::from_slice(payload, ENDIAN).map_err(StateError::Decode),
+        version => Err(StateError::UnsupportedVersion(version)),
+    }
+}
+
+fn state_home() -> std::option::Option<std::path::PathBuf> {
+    #[cfg(target_os = "windows")]
+    let state_home = std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from);
+    #[cfg(not(target_os = "windows"))]
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".local/state"))
+        });
+
+    state_home.map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+}
+
+/// Path of the state file for a wallpaper directory, nothing is created until it is written
+pub fn locate(wallpaper_dir_path: &std::path::Path) -> Result<std::path::PathBuf, std::io::Error> {
+    let canonical_path = wallpaper_dir_path.canonicalize()?;
+    let state_home = state_home().ok_or(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "no state directory, neither XDG_STATE_HOME nor HOME is set",
+    ))?;
+
+    // The directory name keeps the path readable while the hash keeps it unique
+    let key_hash = crate::fnv1a(
+        crate::FNV_OFFSET_BASIS,
+        canonical_path.as_os_str().as_encoded_bytes(),
+    );
+    let key_name = canonical_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let state_dir = state_home.join(format!("{key_name}-{key_hash:016x}"));
+    Ok(state_dir.join(STATE_FILE_NAME))
+}
+
+/// Name of this machine, made safe to use in a file name
+pub fn host_name() -> String {
+    #[cfg(target_os = "windows")]
+    let host_name = std::env::var("COMPUTERNAME").ok();
+    #[cfg(not(target_os = "windows"))]
+    let host_name = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok());
+
+    let host_name: String = host_name
+        .unwrap_or_default()
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if host_name.is_empty() {
+        "localhost".to_owned()
+    } else {
+        host_name
+    }
+}
+
+/// Path of the host's own state next to `state_path`
+pub fn host_path(state_path: &std::path::Path, host_name: &str) -> std::path::PathBuf {
+    state_path.with_file_name(format!("state.{host_name}.bin"))
+}
+
+/// States written by older releases that the host's state takes over: the one shared by every
+/// host next to it, and the one kept inside the wallpaper directory
+fn legacy_paths(
+    wallpaper_dir_path: &std::path::Path,
+    host_state_path: &std::path::Path,
+) -> [std::path::PathBuf; 2] {
+    [
+        host_state_path.with_file_name(STATE_FILE_NAME),
+        wallpaper_dir_path.join(STATE_FILE_NAME),
+    ]
+}
+
+/// Path the state is read from, the host's own state or, until `migrate` moved it there, the
+/// state an older release left behind
+pub fn readable_path(
+    wallpaper_dir_path: &std::path::Path,
+    host_state_path: &std::path::Path,
+) -> std::path::PathBuf {
+    if host_state_path.exists() {
+        return host_state_path.to_path_buf();
+    }
+    legacy_paths(wallpaper_dir_path, host_state_path)
+        .into_iter()
+        .find(|path| path.exists())
+        .unwrap_or_else(|| host_state_path.to_path_buf())
+}
+
+/// Moves the state an older release left behind to the host's own state, with its backups. The
+/// shared state becomes the state of the first host to run, so it is only counted once. Has to
+/// be called with the lock held, another invocation could be reading the state being moved
+pub fn migrate(
+    wallpaper_dir_path: &std::path::Path,
+    host_state_path: &std::path::Path,
+) -> Result<(), std::io::Error> {
+    if host_state_path.exists() {
+        return Ok(());
+    }
+    let Some(old_state_path) = legacy_paths(wallpaper_dir_path, host_state_path)
+        .into_iter()
+        .find(|path| path.exists())
+    else {
+        return Ok(());
+    };
+    println!(
+        "Moving state from {} to {}",
+        old_state_path.display(),
+        host_state_path.display()
+    );
+
+    // Copied rather than renamed, the state inside the wallpaper directory may be on another file
+    // system or read-only, in which case leaving it behind is harmless
+    let mut paths = vec![(old_state_path.clone(), host_state_path.to_path_buf())];
+    for generation in 0..BACKUP_COUNT {
+        paths.push((
+            backup_path(&old_state_path, generation),
+            backup_path(host_state_path, generation),
+        ));
+    }
+    for (old_path, path) in &paths {
+        if old_path.exists() {
+            std::fs::copy(old_path, path)?;
+        }
+    }
+    for (old_path, _) in paths {
+        let _ = std::fs::remove_file(old_path);
+    }
+
+    Ok(())
+}
+
+/// Host names and state paths of the other hosts keeping a state next to `host_state_path`
+pub fn peer_paths(host_state_path: &std::path::Path) -> Vec<(String, std::path::PathBuf)> {
+    let Some(state_dir) = host_state_path.parent() else {
+        return vec![];
+    };
+    let state_dir = if state_dir.as_os_str().is_empty() {
+        std::path::Path::new(".")
+    } else {
+        state_dir
+    };
+    let Ok(entries) = state_dir.read_dir() else {
+        return vec![];
+    };
+
+    let mut peers: Vec<(String, std::path::PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name() != host_state_path.file_name())
+        .filter_map(|path| {
+            let host_name = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("state.")?
+                .strip_suffix(".bin")?
+                .to_owned();
+            Some((host_name, path))
+        })
+        .collect();
+    peers.sort();
+
+    peers
+}
+
+/// Exclusive advisory lock on the state, released when dropped
+pub struct StateLock {
+    _file: std::fs::File,
+}
+
+/// Blocks until no other invocation is reading and modifying the state, creating the state
+/// directory if needed
+pub fn lock(state_path: &std::path::Path) -> Result<StateLock, std::io::Error> {
+    if let Some(state_dir) = state_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(state_dir)?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(state_path.with_extension("bin.lock"))?;
+    file.lock()?;
+
+    Ok(StateLock { _file: file })
+}
+
+/// Path of a previous state, generation 0 being the most recent one
+fn backup_path(state_path: &std::path::Path, generation: usize) -> std::path::PathBuf {
+    match generation {
+        0 => state_path.with_extension("bin.bak"),
+        generation => state_path.with_extension(format!("bin.bak.{generation}")),
+    }
+}
+
+/// Start of the file, long enough to hold the header
+fn read_header(path: &std::path::Path) -> std::option::Option<Vec<u8>> {
+    use std::io::Read;
+
+    let mut header = vec![];
+    std::fs::File::open(path)
+        .and_then(|file| file.take(MAGIC.len() as u64 + 4).read_to_end(&mut header))
+        .ok()?;
+    Some(header)
+}
+
+/// Format version in the header of the file, `None` if it has none
+fn header_version(header: &[u8]) -> std::option::Option<u32> {
+    let version = header.strip_prefix(MAGIC)?;
+    Some(u32::from_le_bytes(version.try_into().ok()?))
+}
+
+/// Whether the file starts like a state this release can read. Only the header is looked at,
+/// states written before it was introduced are decoded in full
+fn is_readable(path: &std::path::Path) -> bool {
+    let Some(header) = read_header(path) else {
+        return false;
+    };
+
+    match header_version(&header) {
+        Some(version) => version <= CURRENT_VERSION,
+        None => read_file(path).is_some(),
+    }
+}
+
+/// Fails if the state or one of its backups was written by a newer release. Such a state can't
+/// be read, and replacing it would lose what the newer release recorded
+pub fn check_version(state_path: &std::path::Path) -> Result<(), StateError> {
+    let paths = std::iter::once(state_path.to_path_buf())
+        .chain((0..BACKUP_COUNT).map(|generation| backup_path(state_path, generation)));
+    for path in paths {
+        let version = read_header(&path).and_then(|header| header_version(&header));
+        if let Some(version) = version.filter(|&version| version > CURRENT_VERSION) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+    }
+    Ok(())
+}
+
+fn read_file(path: &std::path::Path) -> std::option::Option<Vec<Wallpaper>> {
+    let state = match std::fs::read(path) {
+        Ok(state) => state,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            eprintln!("Warning: can't read {}: {err}", path.display());
+            return None;
+        }
+    };
+
+    match decode(&state) {
+        Ok(wallpapers) => Some(wallpapers),
+        Err(StateError::UnsupportedVersion(version)) => {
+            eprintln!(
+                "Warning: {} was written by a newer release, state version {version}",
+                path.display()
+            );
+            None
+        }
+        Err(err) => {
+            eprintln!("Warning: {} is corrupt: {err:?}", path.display());
+            None
+        }
+    }
+}
+
+/// Reads the state, falling back to the backups, the most recent first, if the state is missing
+/// or corrupt
+pub fn read(state_path: &std::path::Path) -> std::option::Option<Vec<Wallpaper>> {
+    read_file(state_path).or_else(|| {
+        (0..BACKUP_COUNT).find_map(|generation| {
+            let backup_path = backup_path(state_path, generation);
+            let wallpapers = read_file(&backup_path)?;
+            eprintln!("Warning: using backup state {}", backup_path.display());
+            Some(wallpapers)
+        })
+    })
+}
+
+/// Atomically replaces the state, moving the previous one to the backups. A state that can't be
+/// read isn't kept, it would push out a backup that can. Nothing is replaced if a newer release
+/// wrote the state or a backup, see `check_version`
+pub fn write(state_path: &std::path::Path, wallpapers: &[Wallpaper]) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    if let Err(StateError::UnsupportedVersion(version)) = check_version(state_path) {
+        return Err(std::io::Error::other(format!(
+            "{} holds state version {version} of a newer release",
+            state_path.display()
+        )));
+    }
+
+    let tmp_path = state_path.with_extension("bin.tmp");
+    let mut tmp = std::fs::File::create(&tmp_path)?;
+    tmp.write_all(&encode(wallpapers))?;
+    tmp.sync_all()?;
+    drop(tmp);
+
+    if is_readable(state_path) {
+        for generation in (1..BACKUP_COUNT).rev() {
+            let newer = backup_path(state_path, generation - 1);
+            if newer.exists() {
+                std::fs::rename(newer, backup_path(state_path, generation))?;
+            }
+        }
+        std::fs::rename(state_path, backup_path(state_path, 0))?;
+    }
+    std::fs::rename(&tmp_path, state_path)?;
+
+    // Persist the renames themselves, directories can't be opened as files on Windows
+    #[cfg(not(target_os = "windows"))]
+    if let Some(dir) = state_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
@@ -44,6 +44,16 @@ impl WallSetter {
         self.set_wallpaper_windows(wallpaper)
     }
 
+    /// Name of the program actually used to set wallpapers
+    pub fn backend(&self) -> &'static str {
+        "SystemParametersInfoW"
+    }
+
+    /// Monitors the wallpaper is set on, Windows spans a single wallpaper across all of them
+    pub fn monitor(&self) -> &'static str {
+        "all"
+    }
+
     pub fn is_running(&self) -> bool {
         let output = std::process::Command::new("tasklist")
             .arg("/fo")
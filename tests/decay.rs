@@ -0,0 +1,47 @@
+use wallrustler::collection::CollectionWeights;
+use wallrustler::pick_random_wallpaper;
+use wallrustler::selection::{new_rng, Constraints, Decay, StrategyKind, Weighting};
+use wallrustler::Wallpaper;
+
+/// Collection `b` is used far more than `a`, its weights must still tell its wallpapers apart
+#[test]
+fn decay_is_relative_to_the_collection_picked_from() {
+    let wallpapers: Vec<Wallpaper> = [("a/x.jpg", 0), ("b/low.jpg", 800), ("b/high.jpg", 820)]
+        .into_iter()
+        .map(|(file_name, count)| {
+            let mut wallpaper = Wallpaper::new(file_name.to_owned());
+            wallpaper.count = count;
+            wallpaper
+        })
+        .collect();
+    let collection_weights = CollectionWeights {
+        weights: vec![("a".to_owned(), 1.0), ("b".to_owned(), 1.0)],
+    };
+    let shares = collection_weights.resolve(
+        wallpapers
+            .iter()
+            .map(|wallpaper| ("walls", wallpaper.file_name.as_str())),
+    );
+    let weighting = Weighting {
+        decay: Decay::Softmax(1.0),
+        ..Weighting::default()
+    };
+    let mut strategy = StrategyKind::Decay.build(weighting);
+    let mut rng = new_rng(Some(42));
+
+    let mut picks = [0; 3];
+    for _ in 0..200 {
+        let index = pick_random_wallpaper(
+            &mut wallpapers.clone(),
+            strategy.as_mut(),
+            &Constraints::default(),
+            &shares,
+            &mut rng,
+        )
+        .unwrap();
+        picks[index] += 1;
+    }
+
+    assert!(picks[1] > 0);
+    assert_eq!(picks[2], 0);
+}
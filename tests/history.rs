@@ -0,0 +1,23 @@
+use wallrustler::history::{format_timestamp, parse_timestamp};
+
+#[test]
+fn timestamps_are_parsed_in_utc_unless_offset() {
+    assert_eq!(parse_timestamp("2024-03-01"), Some(1_709_251_200));
+    assert_eq!(parse_timestamp("2024-03-01T14:30"), Some(1_709_303_400));
+    assert_eq!(parse_timestamp("2024-03-01T14:30Z"), Some(1_709_303_400));
+    assert_eq!(
+        parse_timestamp("2024-03-01T16:30+02:00"),
+        Some(1_709_303_400)
+    );
+    assert_eq!(
+        parse_timestamp("2024-03-01 09:30:00-05:00"),
+        Some(1_709_303_400)
+    );
+    assert_eq!(parse_timestamp("2024-03-01T14:30+2"), None);
+    assert_eq!(parse_timestamp("2024-03-01T14:30+24:00"), None);
+}
+
+#[test]
+fn timestamps_are_formatted_in_utc() {
+    assert_eq!(format_timestamp(1_709_303_400), "2024-03-01 14:30:00 UTC");
+}
@@ -0,0 +1,36 @@
+use wallrustler::state;
+use wallrustler::Wallpaper;
+
+const THREADS: usize = 4;
+const INCREMENTS: usize = 25;
+
+#[test]
+fn locked_increments_are_not_lost() {
+    let state_dir = std::env::temp_dir().join(format!("wallrustler-lock-{}", std::process::id()));
+    let state_path = state_dir.join(state::STATE_FILE_NAME);
+    {
+        let _lock = state::lock(&state_path).unwrap();
+        state::write(&state_path, &[Wallpaper::new("a.jpg".to_owned())]).unwrap();
+    }
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let state_path = state_path.clone();
+            std::thread::spawn(move || {
+                for _ in 0..INCREMENTS {
+                    let _lock = state::lock(&state_path).unwrap();
+                    let mut wallpapers = state::read(&state_path).unwrap();
+                    wallpapers[0].count += 1;
+                    state::write(&state_path, &wallpapers).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let wallpapers = state::read(&state_path).unwrap();
+    std::fs::remove_dir_all(&state_dir).unwrap();
+    assert_eq!(wallpapers[0].count, THREADS * INCREMENTS);
+}
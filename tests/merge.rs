@@ -0,0 +1,108 @@
+use wallrustler::collection::Shares;
+use wallrustler::merge::Peers;
+use wallrustler::selection::{new_rng, Constraints, StrategyKind, Weighting};
+use wallrustler::{pick_random_wallpaper, state, Wallpaper};
+
+fn wallpaper(file_name: &str, count: usize, hash: u64) -> Wallpaper {
+    let mut wallpaper = Wallpaper::new(file_name.to_owned());
+    wallpaper.count = count;
+    wallpaper.hash = Some(hash);
+    wallpaper
+}
+
+fn counts(wallpapers: &[Wallpaper]) -> Vec<(String, usize)> {
+    wallpapers
+        .iter()
+        .map(|wallpaper| (wallpaper.file_name.clone(), wallpaper.count))
+        .collect()
+}
+
+/// Two hosts sharing a state directory, host `b` still knows `c.jpg` under its old name
+#[test]
+fn merged_counts_are_counted_once() {
+    let state_dir = std::env::temp_dir().join(format!("wallrustler-merge-{}", std::process::id()));
+    std::fs::create_dir_all(&state_dir).unwrap();
+    let state_path = state_dir.join(state::STATE_FILE_NAME);
+    let a_path = state::host_path(&state_path, "a");
+    let b_path = state::host_path(&state_path, "b");
+
+    let a_local = vec![
+        wallpaper("a.jpg", 3, 1),
+        wallpaper("b.jpg", 1, 2),
+        wallpaper("c.jpg", 0, 3),
+    ];
+    let b_local = vec![
+        wallpaper("a.jpg", 2, 1),
+        wallpaper("b.jpg", 0, 2),
+        wallpaper("old-c.jpg", 4, 3),
+    ];
+    state::write(&a_path, &a_local).unwrap();
+    state::write(&b_path, &b_local).unwrap();
+
+    // Host `a` picks from the merged counts and saves only its own share
+    let a_peers = Peers::read(&a_path);
+    let mut merged = a_peers.merge(&a_local);
+    let merged_before = counts(&merged);
+    let mut strategy = StrategyKind::Decay.build(Weighting::default());
+    let picked = pick_random_wallpaper(
+        &mut merged,
+        strategy.as_mut(),
+        &Constraints::default(),
+        &Shares::default(),
+        &mut new_rng(Some(42)),
+    )
+    .unwrap();
+    let merged_after = counts(&merged);
+    let a_saved = a_peers.split(&a_local, merged);
+    state::write(&a_path, &a_saved).unwrap();
+
+    // Merging again, from either host, adds every share once
+    let a_remerged = Peers::read(&a_path).merge(&state::read(&a_path).unwrap());
+    let b_remerged = Peers::read(&b_path).merge(&state::read(&b_path).unwrap());
+    std::fs::remove_dir_all(&state_dir).unwrap();
+
+    let expected_before = counts(&[
+        wallpaper("a.jpg", 5, 1),
+        wallpaper("b.jpg", 1, 2),
+        wallpaper("c.jpg", 4, 3),
+    ]);
+    assert_eq!(merged_before, expected_before);
+    let mut expected_after = expected_before;
+    expected_after[picked].1 += 1;
+    assert_eq!(merged_after, expected_after);
+
+    let mut expected_saved = counts(&a_local);
+    expected_saved[picked].1 += 1;
+    assert_eq!(counts(&a_saved), expected_saved);
+    assert_eq!(counts(&a_remerged), expected_after);
+    let mut expected_b = expected_after;
+    expected_b[2].0 = "old-c.jpg".to_owned();
+    assert_eq!(counts(&b_remerged), expected_b);
+}
+
+#[test]
+fn split_without_changes_restores_local_share() {
+    let state_dir = std::env::temp_dir().join(format!("wallrustler-split-{}", std::process::id()));
+    std::fs::create_dir_all(&state_dir).unwrap();
+    let state_path = state_dir.join(state::STATE_FILE_NAME);
+    let a_path = state::host_path(&state_path, "a");
+    let a_local = vec![wallpaper("a.jpg", 3, 1), wallpaper("c.jpg", 1, 3)];
+    state::write(
+        &state::host_path(&state_path, "b"),
+        &[wallpaper("a.jpg", 2, 1), wallpaper("old-c.jpg", 4, 3)],
+    )
+    .unwrap();
+
+    let peers = Peers::read(&a_path);
+    let merged = peers.merge(&a_local);
+    let split = peers.split(&a_local, merged.clone());
+    let split_again = peers.split(&split, peers.merge(&split));
+    std::fs::remove_dir_all(&state_dir).unwrap();
+
+    assert_eq!(
+        counts(&merged),
+        counts(&[wallpaper("a.jpg", 5, 1), wallpaper("c.jpg", 5, 3)])
+    );
+    assert_eq!(counts(&split), counts(&a_local));
+    assert_eq!(counts(&split_again), counts(&a_local));
+}
@@ -0,0 +1,71 @@
+use wallrustler::collection::Shares;
+use wallrustler::pick_random_wallpaper;
+use wallrustler::selection::{new_rng, Constraints, StrategyKind, Weighting};
+use wallrustler::Wallpaper;
+
+const SEED: u64 = 42;
+
+/// File names of `rotations` picks from five fresh wallpapers
+fn picks(strategy: StrategyKind, seed: u64, rotations: usize) -> Vec<String> {
+    let mut wallpapers: Vec<Wallpaper> = ["a.jpg", "b.jpg", "c.jpg", "d.jpg", "e.jpg"]
+        .into_iter()
+        .map(|file_name| Wallpaper::new(file_name.to_owned()))
+        .collect();
+    let mut strategy = strategy.build(Weighting::default());
+    let mut rng = new_rng(Some(seed));
+
+    (0..rotations)
+        .map(|_| {
+            let index = pick_random_wallpaper(
+                &mut wallpapers,
+                strategy.as_mut(),
+                &Constraints::default(),
+                &Shares::default(),
+                &mut rng,
+            )
+            .unwrap();
+            wallpapers[index].file_name.clone()
+        })
+        .collect()
+}
+
+#[test]
+fn decay_picks_are_reproducible() {
+    assert_eq!(
+        picks(StrategyKind::Decay, SEED, 10),
+        [
+            "c.jpg", "e.jpg", "c.jpg", "d.jpg", "a.jpg", "b.jpg", "e.jpg", "d.jpg", "b.jpg",
+            "b.jpg"
+        ]
+    );
+}
+
+#[test]
+fn uniform_picks_are_reproducible() {
+    assert_eq!(
+        picks(StrategyKind::Uniform, SEED, 10),
+        [
+            "c.jpg", "e.jpg", "e.jpg", "e.jpg", "e.jpg", "a.jpg", "e.jpg", "d.jpg", "c.jpg",
+            "c.jpg"
+        ]
+    );
+}
+
+#[test]
+fn shuffle_bag_picks_are_reproducible() {
+    assert_eq!(
+        picks(StrategyKind::ShuffleBag, SEED, 10),
+        [
+            "c.jpg", "d.jpg", "e.jpg", "b.jpg", "a.jpg", "e.jpg", "b.jpg", "d.jpg", "c.jpg",
+            "a.jpg"
+        ]
+    );
+}
+
+#[test]
+fn different_seeds_give_different_picks() {
+    assert_ne!(
+        picks(StrategyKind::Uniform, SEED, 10),
+        picks(StrategyKind::Uniform, SEED + 1, 10)
+    );
+}
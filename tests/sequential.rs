@@ -0,0 +1,30 @@
+use wallrustler::collection::Shares;
+use wallrustler::pick_random_wallpaper;
+use wallrustler::selection::{new_rng, Constraints, StrategyKind, Weighting};
+use wallrustler::Wallpaper;
+
+/// Two sources holding files of the same names, one after the other as in a `Union`
+#[test]
+fn sequential_walks_through_same_names_in_every_source() {
+    let mut wallpapers: Vec<Wallpaper> = ["a.jpg", "b.jpg", "a.jpg", "b.jpg"]
+        .into_iter()
+        .map(|file_name| Wallpaper::new(file_name.to_owned()))
+        .collect();
+    let mut strategy = StrategyKind::Sequential.build(Weighting::default());
+    let mut rng = new_rng(Some(0));
+
+    let picks: Vec<usize> = (0..6)
+        .map(|_| {
+            pick_random_wallpaper(
+                &mut wallpapers,
+                strategy.as_mut(),
+                &Constraints::default(),
+                &Shares::default(),
+                &mut rng,
+            )
+            .unwrap()
+        })
+        .collect();
+
+    assert_eq!(picks, [0, 2, 1, 3, 0, 2]);
+}
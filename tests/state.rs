@@ -0,0 +1,111 @@
+use wallrustler::state;
+use wallrustler::{Wallpaper, DEFAULT_RATING};
+
+/// Decodes a fixture written in the layout of an earlier version
+fn decode(fixture: &[u8]) -> Vec<Wallpaper> {
+    state::decode(fixture).unwrap()
+}
+
+/// Checks the fields every layout since version 1 has
+fn assert_v1_fields(wallpaper: &Wallpaper) {
+    assert_eq!(wallpaper.file_name, "a.jpg");
+    assert_eq!(wallpaper.count, 7);
+    assert!(!wallpaper.in_bag);
+    assert_eq!(wallpaper.last_shown, Some(1_700_000_000));
+    assert_eq!(wallpaper.rating, 5);
+    assert!(wallpaper.banned);
+    assert_eq!(wallpaper.rotations_since_shown, 3);
+    assert_eq!(wallpaper.hash, Some(0xdead_beef));
+    assert_eq!(wallpaper.size, 1234);
+    assert_eq!(wallpaper.mtime, 1_600_000_000);
+}
+
+#[test]
+fn baseline_state_decodes() {
+    let wallpapers = decode(include_bytes!("fixtures/state_v0.bin"));
+
+    assert_eq!(wallpapers.len(), 2);
+    assert_eq!(wallpapers[0].file_name, "a.jpg");
+    assert_eq!(wallpapers[0].count, 7);
+    assert_eq!(wallpapers[1].file_name, "nature/b.png");
+    assert_eq!(wallpapers[1].count, 2);
+    assert!(wallpapers[0].in_bag);
+    assert_eq!(wallpapers[0].rating, DEFAULT_RATING);
+    assert_eq!(wallpapers[0].removed, None);
+}
+
+#[test]
+fn v1_state_decodes() {
+    let wallpapers = decode(include_bytes!("fixtures/state_v1.bin"));
+
+    assert_v1_fields(&wallpapers[0]);
+    assert_eq!(wallpapers[0].seconds_shown, 0);
+}
+
+#[test]
+fn v2_state_decodes() {
+    let wallpapers = decode(include_bytes!("fixtures/state_v2.bin"));
+
+    assert_v1_fields(&wallpapers[0]);
+    assert_eq!(wallpapers[0].seconds_shown, 900);
+    assert_eq!(wallpapers[0].edited, 0);
+}
+
+#[test]
+fn v3_state_decodes() {
+    let wallpapers = decode(include_bytes!("fixtures/state_v3.bin"));
+
+    assert_v1_fields(&wallpapers[0]);
+    assert_eq!(wallpapers[0].seconds_shown, 900);
+    assert_eq!(wallpapers[0].edited, 1_650_000_000);
+    assert_eq!(wallpapers[0].removed, None);
+}
+
+#[test]
+fn v4_state_decodes() {
+    let wallpapers = decode(include_bytes!("fixtures/state_v4.bin"));
+
+    assert_v1_fields(&wallpapers[0]);
+    assert_eq!(wallpapers[0].seconds_shown, 900);
+    assert_eq!(wallpapers[0].edited, 1_650_000_000);
+    assert_eq!(wallpapers[0].removed, Some(1_690_000_000));
+    assert_eq!(wallpapers[0].format, None);
+}
+
+#[test]
+fn current_state_round_trips() {
+    let mut wallpaper = Wallpaper::new("a.jpg".to_owned());
+    wallpaper.count = 7;
+    wallpaper.format = Some(wallrustler::format::ImageFormat::Png);
+
+    let wallpapers = decode(&state::encode(&[wallpaper]));
+
+    assert_eq!(wallpapers[0].count, 7);
+    assert_eq!(
+        wallpapers[0].format,
+        Some(wallrustler::format::ImageFormat::Png)
+    );
+}
+
+#[test]
+fn newer_state_is_not_replaced() {
+    let state_dir = std::env::temp_dir().join(format!("wallrustler-newer-{}", std::process::id()));
+    std::fs::create_dir_all(&state_dir).unwrap();
+    let state_path = state_dir.join(state::STATE_FILE_NAME);
+    let newer = [b"WRST".as_slice(), &99u32.to_le_bytes(), b"payload"].concat();
+    std::fs::write(&state_path, &newer).unwrap();
+
+    let checked = state::check_version(&state_path);
+    let written = state::write(&state_path, &[Wallpaper::new("a.jpg".to_owned())]);
+    let kept = std::fs::read(&state_path).unwrap();
+    let backed_up = state_dir.join("state.bin.bak").exists();
+    std::fs::remove_dir_all(&state_dir).unwrap();
+
+    assert!(matches!(
+        checked,
+        Err(state::StateError::UnsupportedVersion(99))
+    ));
+    assert!(written.is_err());
+    assert_eq!(kept, newer);
+    assert!(!backed_up);
+}